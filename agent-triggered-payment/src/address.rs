@@ -0,0 +1,143 @@
+use ethers::core::types::H160;
+use ethers::core::utils::to_checksum;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum AddressError {
+    #[error("address must start with 0x: {0}")]
+    MissingPrefix(String),
+    #[error("address must be 40 hex characters: {0}")]
+    InvalidLength(String),
+    #[error("address contains non-hex characters: {0}")]
+    InvalidHex(String),
+    #[error("address fails EIP-55 checksum: {0}")]
+    BadChecksum(String),
+}
+
+/// Validated 20-byte EVM address.
+///
+/// Mixed-case input must carry a valid EIP-55 checksum; all-lowercase and
+/// all-uppercase input is accepted as unchecksummed. Displays checksummed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Address(H160);
+
+impl Address {
+    pub const ZERO: Self = Self(H160([0; 20]));
+
+    pub fn from_h160(address: H160) -> Self {
+        Self(address)
+    }
+
+    pub fn as_h160(&self) -> H160 {
+        self.0
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+
+    /// EIP-55 checksummed form
+    pub fn checksummed(&self) -> String {
+        to_checksum(&self.0, None)
+    }
+
+    /// Abbreviated form for logs, e.g. `0xAbCd...5678`
+    pub fn short(&self) -> String {
+        let full = self.checksummed();
+        format!("{}...{}", &full[..6], &full[full.len() - 4..])
+    }
+}
+
+impl FromStr for Address {
+    type Err = AddressError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let hex = s
+            .strip_prefix("0x")
+            .or_else(|| s.strip_prefix("0X"))
+            .ok_or_else(|| AddressError::MissingPrefix(s.to_string()))?;
+        if hex.len() != 40 {
+            return Err(AddressError::InvalidLength(s.to_string()));
+        }
+        if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(AddressError::InvalidHex(s.to_string()));
+        }
+
+        let address = Self(H160::from_str(hex).map_err(|_| AddressError::InvalidHex(s.to_string()))?);
+
+        let is_lower = !hex.bytes().any(|b| b.is_ascii_uppercase());
+        let is_upper = !hex.bytes().any(|b| b.is_ascii_lowercase());
+        if !is_lower && !is_upper && address.checksummed()[2..] != *hex {
+            return Err(AddressError::BadChecksum(s.to_string()));
+        }
+
+        Ok(address)
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.checksummed())
+    }
+}
+
+impl Serialize for Address {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Address {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Example from EIP-55
+    const CHECKSUMMED: &str = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed";
+
+    #[test]
+    fn accepts_a_valid_checksum() {
+        let address: Address = CHECKSUMMED.parse().unwrap();
+        assert_eq!(address.to_string(), CHECKSUMMED);
+    }
+
+    #[test]
+    fn rejects_a_bad_mixed_case_checksum() {
+        let miscased = "0x5aaeb6053F3E94C9b9A09f33669435E7Ef1BeAed";
+        assert_eq!(miscased.parse::<Address>(), Err(AddressError::BadChecksum(miscased.to_string())));
+    }
+
+    #[test]
+    fn accepts_single_case_input_unchecked() {
+        let lower: Address = CHECKSUMMED.to_lowercase().parse().unwrap();
+        let upper: Address = format!("0x{}", CHECKSUMMED[2..].to_uppercase()).parse().unwrap();
+        assert_eq!(lower.to_string(), CHECKSUMMED);
+        assert_eq!(upper, lower);
+    }
+
+    #[test]
+    fn rejects_malformed_addresses() {
+        let short = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeA";
+        let not_hex = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAeg";
+        assert_eq!(CHECKSUMMED[2..].parse::<Address>(), Err(AddressError::MissingPrefix(CHECKSUMMED[2..].to_string())));
+        assert_eq!(short.parse::<Address>(), Err(AddressError::InvalidLength(short.to_string())));
+        assert_eq!(not_hex.parse::<Address>(), Err(AddressError::InvalidHex(not_hex.to_string())));
+    }
+
+    #[test]
+    fn short_keeps_the_checksummed_ends() {
+        let address: Address = CHECKSUMMED.to_lowercase().parse().unwrap();
+        assert_eq!(address.short(), "0x5aAe...eAed");
+        assert_eq!(Address::ZERO.short(), "0x0000...0000");
+    }
+}
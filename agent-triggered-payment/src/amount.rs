@@ -0,0 +1,207 @@
+use ethers::core::types::U256;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Decimals of the native token (TCRO), matching `PayStreamStream` wei amounts
+pub const DECIMALS: usize = 18;
+
+/// Symbol of the native token, the unit budgets and payments are in
+pub const SYMBOL: &str = "TCRO";
+
+const WEI_PER_TOKEN: u64 = 1_000_000_000_000_000_000;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum AmountError {
+    #[error("amount is empty")]
+    Empty,
+    #[error("amount cannot be negative: {0}")]
+    Negative(String),
+    #[error("malformed amount: {0}")]
+    Malformed(String),
+    #[error("amount has more than {DECIMALS} decimal places: {0}")]
+    TooPrecise(String),
+    #[error("amount overflows 256 bits: {0}")]
+    Overflow(String),
+}
+
+/// Exact native token amount, stored in wei.
+///
+/// Parses from and formats to decimal token strings (`"0.0001"`), so header
+/// values and contract amounts round-trip without floating point. Parsing
+/// ignores surrounding whitespace and, like ethers' `parseEther`, allows
+/// either side of the decimal point to be empty (`"1."`, `".5"`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Amount(U256);
+
+impl Amount {
+    pub const ZERO: Self = Self(U256([0; 4]));
+
+    pub fn from_wei(wei: U256) -> Self {
+        Self(wei)
+    }
+
+    pub const fn from_wei_u128(wei: u128) -> Self {
+        Self(U256([wei as u64, (wei >> 64) as u64, 0, 0]))
+    }
+
+    /// Whole tokens, e.g. `Amount::from_whole(50)` for 50 TCRO
+    pub fn from_whole(tokens: u64) -> Self {
+        Self(U256::from(tokens) * U256::from(WEI_PER_TOKEN))
+    }
+
+    pub fn wei(&self) -> U256 {
+        self.0
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        self.0.checked_add(other.0).map(Self)
+    }
+
+    pub fn checked_sub(self, other: Self) -> Option<Self> {
+        self.0.checked_sub(other.0).map(Self)
+    }
+
+    pub fn saturating_add(self, other: Self) -> Self {
+        Self(self.0.saturating_add(other.0))
+    }
+
+    pub fn saturating_sub(self, other: Self) -> Self {
+        Self(self.0.saturating_sub(other.0))
+    }
+
+    /// Format with a token symbol, e.g. `"0.0001 TCRO"`
+    pub fn with_symbol(&self, symbol: &str) -> String {
+        format!("{} {}", self, symbol)
+    }
+}
+
+impl FromStr for Amount {
+    type Err = AmountError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err(AmountError::Empty);
+        }
+        if s.starts_with('-') {
+            return Err(AmountError::Negative(s.to_string()));
+        }
+
+        let (int, frac) = s.split_once('.').unwrap_or((s, ""));
+        let is_digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+        if (int.is_empty() && frac.is_empty()) || !is_digits(int) || !is_digits(frac) {
+            return Err(AmountError::Malformed(s.to_string()));
+        }
+        if frac.len() > DECIMALS {
+            return Err(AmountError::TooPrecise(s.to_string()));
+        }
+
+        let overflow = || AmountError::Overflow(s.to_string());
+        let int_wei = if int.is_empty() {
+            U256::zero()
+        } else {
+            U256::from_dec_str(int)
+                .map_err(|_| overflow())?
+                .checked_mul(U256::from(WEI_PER_TOKEN))
+                .ok_or_else(overflow)?
+        };
+
+        // At most 18 digits, so the padded fraction always fits in a u64
+        let frac_wei = if frac.is_empty() {
+            0
+        } else {
+            format!("{:0<width$}", frac, width = DECIMALS)
+                .parse::<u64>()
+                .map_err(|_| AmountError::Malformed(s.to_string()))?
+        };
+
+        int_wei
+            .checked_add(U256::from(frac_wei))
+            .map(Self)
+            .ok_or_else(overflow)
+    }
+}
+
+impl fmt::Display for Amount {
+    /// Decimal token amount with trailing zeros trimmed, like ethers' `formatEther`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (int, frac) = self.0.div_mod(U256::from(WEI_PER_TOKEN));
+        let frac = format!("{:0width$}", frac.as_u64(), width = DECIMALS);
+        let frac = frac.trim_end_matches('0');
+        write!(f, "{}.{}", int, if frac.is_empty() { "0" } else { frac })
+    }
+}
+
+impl Serialize for Amount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Amount {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Result<Amount, AmountError> {
+        s.parse()
+    }
+
+    #[test]
+    fn parses_decimal_token_strings() {
+        assert_eq!(parse("1"), Ok(Amount::from_whole(1)));
+        assert_eq!(parse("0.0001"), Ok(Amount::from_wei_u128(100_000_000_000_000)));
+        assert_eq!(parse("0.000000000000000001"), Ok(Amount::from_wei_u128(1)));
+        assert_eq!(parse(" 1 "), Ok(Amount::from_whole(1)));
+        assert_eq!(parse("1."), Ok(Amount::from_whole(1)));
+        assert_eq!(parse(".5"), Ok(Amount::from_wei_u128(500_000_000_000_000_000)));
+    }
+
+    #[test]
+    fn rejects_malformed_amounts() {
+        assert_eq!(parse("  "), Err(AmountError::Empty));
+        assert_eq!(parse("-1"), Err(AmountError::Negative("-1".to_string())));
+        for malformed in [".", "+1", "1e18", "1.2.3", "0x10", "1 000"] {
+            assert_eq!(parse(malformed), Err(AmountError::Malformed(malformed.to_string())));
+        }
+    }
+
+    #[test]
+    fn rejects_more_than_18_decimals() {
+        assert_eq!(
+            parse("0.0000000000000000001"),
+            Err(AmountError::TooPrecise("0.0000000000000000001".to_string()))
+        );
+    }
+
+    #[test]
+    fn rejects_amounts_past_256_bits() {
+        // U256::MAX is about 1.16e77 wei, or 1.16e59 tokens
+        let too_many_tokens = format!("1{}", "0".repeat(60));
+        assert_eq!(parse(&too_many_tokens), Err(AmountError::Overflow(too_many_tokens.clone())));
+        let too_many_digits = "9".repeat(80);
+        assert_eq!(parse(&too_many_digits), Err(AmountError::Overflow(too_many_digits.clone())));
+    }
+
+    #[test]
+    fn displays_trimmed_and_round_trips() {
+        assert_eq!(Amount::ZERO.to_string(), "0.0");
+        assert_eq!(Amount::from_whole(50).to_string(), "50.0");
+        for s in ["0.0001", "1.5", "123.000000000000000001"] {
+            assert_eq!(parse(s).unwrap().to_string(), s);
+        }
+        assert_eq!(Amount::from_whole(2).with_symbol("TCRO"), "2.0 TCRO");
+    }
+}
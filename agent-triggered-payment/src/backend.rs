@@ -0,0 +1,477 @@
+//! Where payments settle. [`PaymentBackend`] covers what the agent needs
+//! from `PayStreamStream` and the native token: [`PayStreamChain`] settles
+//! over JSON-RPC, and [`MemoryBackend`] keeps a deterministic in-memory
+//! ledger with the contract's accounting for simulations and tests.
+//!
+//! [`PayStreamChain`]: crate::chain::PayStreamChain
+
+use async_trait::async_trait;
+use ethers::core::types::{H256, U256};
+use ethers::core::utils::keccak256;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use crate::address::Address;
+use crate::amount::Amount;
+use crate::chain::{max_fee, CancelledStream, ChainError, CreatedStream, Transfer, CREATE_STREAM_GAS, TRANSFER_GAS};
+use crate::sizing;
+use crate::streams;
+use crate::wallet::AgentWallet;
+
+/// A stream as stored by the contract's `streams(id)`, with its
+/// `getClaimableBalance` at the time it was read
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StreamState {
+    pub stream_id: u64,
+    pub sender: Address,
+    pub recipient: Address,
+    pub total_amount: Amount,
+    /// Wei per second, floored as the contract stores it
+    pub flow_rate: Amount,
+    /// Unix seconds
+    pub start_time: u64,
+    /// Unix seconds
+    pub stop_time: u64,
+    pub amount_withdrawn: Amount,
+    /// Cleared by `cancelStream`; stays set past `stopTime`
+    pub is_active: bool,
+    /// Streamed but not yet withdrawn; zero once inactive
+    pub claimable: Amount,
+    pub metadata: String,
+}
+
+impl StreamState {
+    /// `getClaimableBalance` at `now`
+    pub fn claimable_at(&self, now: u64) -> Amount {
+        if !self.is_active || now < self.start_time {
+            return Amount::ZERO;
+        }
+        if now >= self.stop_time {
+            return self.total_amount.saturating_sub(self.amount_withdrawn);
+        }
+        let streamed = self.flow_rate.wei().saturating_mul(U256::from(now - self.start_time));
+        Amount::from_wei(streamed).saturating_sub(self.amount_withdrawn)
+    }
+
+    /// Deposit not yet streamed, which `cancelStream` would refund
+    pub fn unstreamed(&self) -> Amount {
+        self.total_amount
+            .saturating_sub(self.amount_withdrawn)
+            .saturating_sub(self.claimable)
+    }
+}
+
+/// A mined native transfer, as [`PaymentBackend::find_transfer`] looks it up
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LedgerTransfer {
+    pub tx_hash: H256,
+    pub block_number: u64,
+    pub from: Address,
+    pub to: Address,
+    pub amount: Amount,
+}
+
+/// Settlement layer for agent payments. Errors use [`ChainError`] whichever
+/// backend produced them.
+#[async_trait]
+pub trait PaymentBackend: Send + Sync + 'static {
+    /// Address payments are sent from
+    fn sender(&self) -> Address;
+
+    /// Whether settlement is simulated rather than real
+    fn is_simulated(&self) -> bool {
+        false
+    }
+
+    /// Current Unix time as the settlement layer sees it
+    fn now(&self) -> u64 {
+        streams::now_secs()
+    }
+
+    /// The same backend, paying from another wallet
+    fn with_wallet(&self, wallet: &AgentWallet) -> Self
+    where
+        Self: Sized;
+
+    /// Native token balance of an account
+    async fn balance(&self, account: Address) -> Result<Amount, ChainError>;
+
+    /// Current price of one unit of gas, for estimating fees up front
+    async fn gas_price(&self) -> Result<Amount, ChainError>;
+
+    /// `createStream(recipient, duration, metadata)` with `deposit` attached
+    async fn open_stream(
+        &self,
+        recipient: Address,
+        duration_secs: u64,
+        deposit: Amount,
+        metadata: &str,
+    ) -> Result<CreatedStream, ChainError>;
+
+    /// Native transfer for a per-request payment
+    async fn pay(&self, recipient: Address, amount: Amount) -> Result<Transfer, ChainError>;
+
+    /// `cancelStream(id)`, as the stream's sender or recipient
+    async fn cancel_stream(&self, stream_id: u64) -> Result<CancelledStream, ChainError>;
+
+    /// `streams(id)` and its claimable balance. Fails with
+    /// [`ChainError::UnknownStream`] for IDs that were never opened.
+    async fn stream(&self, stream_id: u64) -> Result<StreamState, ChainError>;
+
+    /// The successful native transfer mined under `tx_hash`, as a service
+    /// checking a per-request payment would look it up. `None` when there
+    /// is no such transaction, or it is pending, reverted or a contract call.
+    async fn find_transfer(&self, tx_hash: H256) -> Result<Option<LedgerTransfer>, ChainError>;
+
+    /// Top `account` up so it can send `value` plus `gas_limit` gas. Returns
+    /// the funding transfer, if one was needed.
+    async fn fund(&self, account: Address, value: Amount, gas_limit: u64) -> Result<Option<Transfer>, ChainError>;
+
+    /// Send this wallet's whole balance, less gas, to `to`. Returns `None`
+    /// when there is nothing to send.
+    async fn sweep(&self, to: Address) -> Result<Option<Transfer>, ChainError>;
+}
+
+/// Contract and balances held in memory. Clones and [`with_wallet`] views
+/// share one ledger; stream IDs, block numbers and transaction hashes are
+/// assigned in sequence. Gas is free unless a price is set with
+/// [`MemoryBackend::with_gas_price`].
+///
+/// [`with_wallet`]: PaymentBackend::with_wallet
+#[derive(Debug, Clone)]
+pub struct MemoryBackend {
+    sender: Address,
+    ledger: Arc<Mutex<MemoryLedger>>,
+}
+
+#[derive(Debug)]
+struct MemoryLedger {
+    balances: HashMap<Address, Amount>,
+    /// Balance of accounts the ledger has not seen yet
+    opening_balance: Amount,
+    streams: BTreeMap<u64, StreamState>,
+    next_stream_id: u64,
+    /// Native transfers by transaction hash
+    transfers: HashMap<H256, LedgerTransfer>,
+    block_number: u64,
+    /// Pinned Unix time; the wall clock when unset
+    time: Option<u64>,
+    gas_price: Amount,
+    /// What [`PaymentBackend::is_simulated`] reports
+    simulated: bool,
+}
+
+impl MemoryLedger {
+    fn now(&self) -> u64 {
+        self.time.unwrap_or_else(streams::now_secs)
+    }
+
+    fn balance(&mut self, account: Address) -> &mut Amount {
+        let opening = self.opening_balance;
+        self.balances.entry(account).or_insert(opening)
+    }
+
+    fn debit(&mut self, account: Address, amount: Amount) -> Result<(), ChainError> {
+        let balance = self.balance(account);
+        if *balance < amount {
+            return Err(ChainError::Send(format!(
+                "insufficient funds: {} has {} TCRO, needs {} TCRO",
+                account.short(),
+                balance,
+                amount
+            )));
+        }
+        *balance = balance.saturating_sub(amount);
+        Ok(())
+    }
+
+    fn credit(&mut self, account: Address, amount: Amount) {
+        let balance = self.balance(account);
+        *balance = balance.saturating_add(amount);
+    }
+
+    /// Mine a block for the next transaction
+    fn mine(&mut self) -> (H256, u64) {
+        self.block_number += 1;
+        let tx_hash = H256(keccak256(self.block_number.to_be_bytes()));
+        (tx_hash, self.block_number)
+    }
+
+    /// Fee for a transaction using all of `gas_limit`
+    fn fee(&self, gas_limit: u64) -> Amount {
+        Amount::from_wei(self.gas_price.wei().saturating_mul(gas_limit.into()))
+    }
+
+    fn transfer(&mut self, from: Address, to: Address, amount: Amount) -> Result<Transfer, ChainError> {
+        let gas_cost = self.fee(TRANSFER_GAS);
+        self.debit(from, amount.saturating_add(gas_cost))?;
+        self.credit(to, amount);
+        let (tx_hash, block_number) = self.mine();
+        self.transfers.insert(
+            tx_hash,
+            LedgerTransfer {
+                tx_hash,
+                block_number,
+                from,
+                to,
+                amount,
+            },
+        );
+        Ok(Transfer {
+            tx_hash,
+            block_number: Some(block_number),
+            amount,
+            gas_cost,
+        })
+    }
+}
+
+impl MemoryBackend {
+    /// Empty ledger paying from `sender`. Stream IDs start at 1, as in the
+    /// contract.
+    pub fn new(sender: Address) -> Self {
+        Self {
+            sender,
+            ledger: Arc::new(Mutex::new(MemoryLedger {
+                balances: HashMap::new(),
+                opening_balance: Amount::ZERO,
+                streams: BTreeMap::new(),
+                next_stream_id: 1,
+                transfers: HashMap::new(),
+                block_number: 0,
+                time: None,
+                gas_price: Amount::ZERO,
+                simulated: true,
+            })),
+        }
+    }
+
+    /// The same ledger, paying from `sender`
+    pub fn for_sender(&self, sender: Address) -> Self {
+        Self {
+            sender,
+            ledger: self.ledger.clone(),
+        }
+    }
+
+    /// Start accounts the ledger has not seen yet with `balance`
+    pub fn with_opening_balance(self, balance: Amount) -> Self {
+        self.ledger.lock().unwrap().opening_balance = balance;
+        self
+    }
+
+    /// Charge transfers and `createStream` `price` per unit of gas, for
+    /// their whole gas limit. Cancels and withdrawals stay free.
+    pub fn with_gas_price(self, price: Amount) -> Self {
+        self.ledger.lock().unwrap().gas_price = price;
+        self
+    }
+
+    /// Report settlement as simulated or not. Set `false` to stand in for a
+    /// real chain, where results are not marked simulated.
+    pub fn reporting_simulated(self, simulated: bool) -> Self {
+        self.ledger.lock().unwrap().simulated = simulated;
+        self
+    }
+
+    /// Add `amount` to an account's balance
+    pub fn credit(&self, account: Address, amount: Amount) {
+        self.ledger.lock().unwrap().credit(account, amount);
+    }
+
+    /// Pin the ledger's clock to `unix_secs`
+    pub fn set_time(&self, unix_secs: u64) {
+        self.ledger.lock().unwrap().time = Some(unix_secs);
+    }
+
+    /// Move the ledger's clock forward, pinning it first if it follows the
+    /// wall clock
+    pub fn advance(&self, secs: u64) {
+        let mut ledger = self.ledger.lock().unwrap();
+        ledger.time = Some(ledger.now() + secs);
+    }
+
+    /// Every stream opened so far, by ID
+    pub fn streams(&self) -> Vec<StreamState> {
+        let ledger = self.ledger.lock().unwrap();
+        let now = ledger.now();
+        ledger
+            .streams
+            .values()
+            .map(|stream| StreamState {
+                claimable: stream.claimable_at(now),
+                ..stream.clone()
+            })
+            .collect()
+    }
+
+    /// `withdrawFromStream(id)` as the stream's recipient
+    pub fn withdraw(&self, stream_id: u64) -> Result<Transfer, ChainError> {
+        let mut ledger = self.ledger.lock().unwrap();
+        let now = ledger.now();
+        let stream = ledger
+            .streams
+            .get_mut(&stream_id)
+            .filter(|stream| stream.is_active)
+            .ok_or(ChainError::UnknownStream(stream_id))?;
+        if stream.recipient != self.sender {
+            return Err(ChainError::Contract("caller is not the recipient".to_string()));
+        }
+        let claimable = stream.claimable_at(now);
+        if claimable.is_zero() {
+            return Err(ChainError::Contract("no funds to withdraw".to_string()));
+        }
+        stream.amount_withdrawn = stream.amount_withdrawn.saturating_add(claimable);
+        let recipient = stream.recipient;
+
+        ledger.credit(recipient, claimable);
+        let (tx_hash, block_number) = ledger.mine();
+        Ok(Transfer {
+            tx_hash,
+            block_number: Some(block_number),
+            amount: claimable,
+            gas_cost: Amount::ZERO,
+        })
+    }
+}
+
+#[async_trait]
+impl PaymentBackend for MemoryBackend {
+    fn sender(&self) -> Address {
+        self.sender
+    }
+
+    fn is_simulated(&self) -> bool {
+        self.ledger.lock().unwrap().simulated
+    }
+
+    /// The ledger's clock
+    fn now(&self) -> u64 {
+        self.ledger.lock().unwrap().now()
+    }
+
+    fn with_wallet(&self, wallet: &AgentWallet) -> Self {
+        self.for_sender(wallet.address())
+    }
+
+    async fn balance(&self, account: Address) -> Result<Amount, ChainError> {
+        Ok(*self.ledger.lock().unwrap().balance(account))
+    }
+
+    async fn gas_price(&self) -> Result<Amount, ChainError> {
+        Ok(self.ledger.lock().unwrap().gas_price)
+    }
+
+    async fn open_stream(
+        &self,
+        recipient: Address,
+        duration_secs: u64,
+        deposit: Amount,
+        metadata: &str,
+    ) -> Result<CreatedStream, ChainError> {
+        let size = sizing::validate(recipient, deposit, duration_secs, None)?;
+        let mut ledger = self.ledger.lock().unwrap();
+        let gas_cost = ledger.fee(CREATE_STREAM_GAS);
+        ledger.debit(self.sender, deposit.saturating_add(gas_cost))?;
+
+        let stream_id = ledger.next_stream_id;
+        ledger.next_stream_id += 1;
+        let start_time = ledger.now();
+        let stop_time = start_time + duration_secs;
+        ledger.streams.insert(
+            stream_id,
+            StreamState {
+                stream_id,
+                sender: self.sender,
+                recipient,
+                total_amount: deposit,
+                flow_rate: size.flow_rate,
+                start_time,
+                stop_time,
+                amount_withdrawn: Amount::ZERO,
+                is_active: true,
+                claimable: Amount::ZERO,
+                metadata: metadata.to_string(),
+            },
+        );
+
+        let (tx_hash, block_number) = ledger.mine();
+        Ok(CreatedStream {
+            stream_id,
+            tx_hash,
+            block_number: Some(block_number),
+            total_amount: deposit,
+            start_time,
+            stop_time,
+            gas_cost,
+        })
+    }
+
+    async fn pay(&self, recipient: Address, amount: Amount) -> Result<Transfer, ChainError> {
+        self.ledger.lock().unwrap().transfer(self.sender, recipient, amount)
+    }
+
+    async fn cancel_stream(&self, stream_id: u64) -> Result<CancelledStream, ChainError> {
+        let mut ledger = self.ledger.lock().unwrap();
+        let now = ledger.now();
+        let stream = ledger
+            .streams
+            .get_mut(&stream_id)
+            .filter(|stream| stream.is_active)
+            .ok_or(ChainError::UnknownStream(stream_id))?;
+        if self.sender != stream.sender && self.sender != stream.recipient {
+            return Err(ChainError::Contract("caller cannot cancel this stream".to_string()));
+        }
+
+        let payout = stream.claimable_at(now);
+        let refund = stream.total_amount.saturating_sub(stream.amount_withdrawn).saturating_sub(payout);
+        stream.is_active = false;
+        let (sender, recipient) = (stream.sender, stream.recipient);
+
+        ledger.credit(recipient, payout);
+        ledger.credit(sender, refund);
+        let (tx_hash, block_number) = ledger.mine();
+        Ok(CancelledStream {
+            stream_id,
+            tx_hash,
+            block_number: Some(block_number),
+            refund,
+            payout,
+            gas_cost: Amount::ZERO,
+        })
+    }
+
+    async fn stream(&self, stream_id: u64) -> Result<StreamState, ChainError> {
+        let ledger = self.ledger.lock().unwrap();
+        let stream = ledger.streams.get(&stream_id).ok_or(ChainError::UnknownStream(stream_id))?;
+        Ok(StreamState {
+            claimable: stream.claimable_at(ledger.now()),
+            ..stream.clone()
+        })
+    }
+
+    async fn find_transfer(&self, tx_hash: H256) -> Result<Option<LedgerTransfer>, ChainError> {
+        Ok(self.ledger.lock().unwrap().transfers.get(&tx_hash).copied())
+    }
+
+    async fn fund(&self, account: Address, value: Amount, gas_limit: u64) -> Result<Option<Transfer>, ChainError> {
+        let mut ledger = self.ledger.lock().unwrap();
+        let needed = value.saturating_add(max_fee(ledger.gas_price, gas_limit));
+        let balance = *ledger.balance(account);
+        if balance >= needed {
+            return Ok(None);
+        }
+        ledger.transfer(self.sender, account, needed.saturating_sub(balance)).map(Some)
+    }
+
+    async fn sweep(&self, to: Address) -> Result<Option<Transfer>, ChainError> {
+        let mut ledger = self.ledger.lock().unwrap();
+        let fee = ledger.fee(TRANSFER_GAS);
+        let balance = *ledger.balance(self.sender);
+        if balance <= fee {
+            return Ok(None);
+        }
+        ledger.transfer(self.sender, to, balance.saturating_sub(fee)).map(Some)
+    }
+}
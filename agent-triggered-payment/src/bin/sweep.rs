@@ -0,0 +1,96 @@
+//! Sweep leftover balances from agents' per-service HD child addresses back
+//! to each agent's own address, and optionally on to the fleet root
+//! (account 0).
+//!
+//! ```bash
+//! AGENT_MNEMONIC="..." PAYSTREAM_CONTRACT=0x... cargo run --bin sweep -- 0 1 [--gap 20] [--to-root]
+//! ```
+
+use dotenv::dotenv;
+use tracing::{error, info, Level};
+use tracing_subscriber::FmtSubscriber;
+
+use paystream_cro::amount::Amount;
+use paystream_cro::chain::{ChainConfig, PayStreamChain};
+use paystream_cro::wallet::{HdWallet, MNEMONIC_ENV};
+
+/// Unused children to scan past before assuming none follow
+const DEFAULT_GAP: u32 = 20;
+
+struct Args {
+    accounts: Vec<u32>,
+    gap: u32,
+    to_root: bool,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = Args {
+        accounts: Vec::new(),
+        gap: DEFAULT_GAP,
+        to_root: false,
+    };
+    let mut iter = std::env::args().skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--gap" => {
+                let value = iter.next().ok_or("--gap needs a value")?;
+                args.gap = value.parse().map_err(|e| format!("--gap {}: {}", value, e))?;
+            }
+            "--to-root" => args.to_root = true,
+            account => args
+                .accounts
+                .push(account.parse().map_err(|e| format!("account {}: {}", account, e))?),
+        }
+    }
+    if args.accounts.is_empty() {
+        args.accounts.push(0);
+    }
+    Ok(args)
+}
+
+async fn run(args: Args) -> Result<Amount, Box<dyn std::error::Error>> {
+    let hd = HdWallet::from_env(MNEMONIC_ENV)?;
+    let config = ChainConfig::from_env()?;
+    let root = PayStreamChain::connect(&config, &hd.agent(0)?).await?;
+
+    let mut total = Amount::ZERO;
+    for account in args.accounts {
+        let agent = root.with_wallet(&hd.agent(account)?);
+        info!("🤖 Account {} ({})", account, agent.sender().short());
+
+        for transfer in agent.sweep_hd_children(&hd, account, args.gap).await? {
+            total = total.saturating_add(transfer.amount);
+        }
+
+        if args.to_root && account != 0 {
+            if let Some(transfer) = agent.sweep(root.sender()).await? {
+                info!("🧹 Swept {} TCRO to root {}", transfer.amount, root.sender().short());
+                total = total.saturating_add(transfer.amount);
+            }
+        }
+    }
+    Ok(total)
+}
+
+#[tokio::main]
+async fn main() {
+    dotenv().ok();
+    let subscriber = FmtSubscriber::builder().with_max_level(Level::INFO).finish();
+    tracing::subscriber::set_global_default(subscriber).expect("Failed to set subscriber");
+
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            error!("❌ {}", e);
+            std::process::exit(2);
+        }
+    };
+
+    match run(args).await {
+        Ok(total) => info!("✅ Swept {} in total", total.with_symbol("TCRO")),
+        Err(e) => {
+            error!("❌ Sweep failed: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
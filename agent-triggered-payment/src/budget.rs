@@ -0,0 +1,340 @@
+//! Daily spending limit. Payments reserve their amount before they are
+//! submitted, so concurrent fetches cannot overspend together, and the
+//! reservation is released if the payment fails.
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::Mutex;
+use thiserror::Error;
+
+use crate::amount::Amount;
+
+/// A payment that would take spending past the limit
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("spending {spend} TCRO would exceed the remaining {remaining} TCRO")]
+pub struct BudgetExceeded {
+    pub spend: Amount,
+    pub remaining: Amount,
+}
+
+/// Period a budget applies to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BudgetWindow {
+    /// Resets at midnight in the given time zone
+    CalendarDay(Tz),
+    /// Counts spend from the last 24 hours
+    Rolling24h,
+}
+
+impl Default for BudgetWindow {
+    fn default() -> Self {
+        Self::CalendarDay(Tz::UTC)
+    }
+}
+
+impl BudgetWindow {
+    /// Earliest spend that still counts at `now`
+    fn start(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        match self {
+            Self::CalendarDay(tz) => {
+                let midnight = now.with_timezone(tz).date_naive().and_hms_opt(0, 0, 0).expect("midnight exists");
+                // Midnight can be skipped or repeated by a DST change
+                tz.from_local_datetime(&midnight)
+                    .earliest()
+                    .map(|start| start.with_timezone(&Utc))
+                    .unwrap_or(now - Duration::hours(24))
+            }
+            Self::Rolling24h => now - Duration::hours(24),
+        }
+    }
+}
+
+/// Identifies a committed payment, so a later refund can be credited to it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SpendId(u64);
+
+#[derive(Debug, Default)]
+struct Ledger {
+    /// Settled payments, oldest first
+    spent: VecDeque<(SpendId, DateTime<Utc>, Amount)>,
+    reserved: Amount,
+    next_id: u64,
+}
+
+impl Ledger {
+    fn prune(&mut self, start: DateTime<Utc>) {
+        while self.spent.front().is_some_and(|(_, at, _)| *at < start) {
+            self.spent.pop_front();
+        }
+    }
+
+    fn spent(&self) -> Amount {
+        self.spent.iter().fold(Amount::ZERO, |total, (_, _, amount)| total.saturating_add(*amount))
+    }
+}
+
+/// Source of the current time for a [`Budget`]
+pub type Clock = Box<dyn Fn() -> DateTime<Utc> + Send + Sync>;
+
+/// Spending limit over a [`BudgetWindow`]
+pub struct Budget {
+    limit: Amount,
+    window: BudgetWindow,
+    ledger: Mutex<Ledger>,
+    clock: Clock,
+}
+
+impl fmt::Debug for Budget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Budget")
+            .field("limit", &self.limit)
+            .field("window", &self.window)
+            .field("ledger", &self.ledger)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Budget {
+    pub fn new(limit: Amount, window: BudgetWindow) -> Self {
+        Self {
+            limit,
+            window,
+            ledger: Mutex::new(Ledger::default()),
+            clock: Box::new(Utc::now),
+        }
+    }
+
+    /// Date spend and find the window's start by `clock` instead of the
+    /// system time
+    pub fn with_clock(mut self, clock: impl Fn() -> DateTime<Utc> + Send + Sync + 'static) -> Self {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    pub fn limit(&self) -> Amount {
+        self.limit
+    }
+
+    pub fn window(&self) -> BudgetWindow {
+        self.window
+    }
+
+    /// Settled spend in the current window
+    pub fn spent(&self) -> Amount {
+        self.ledger_now().spent()
+    }
+
+    /// Amount held by payments still in flight
+    pub fn reserved(&self) -> Amount {
+        self.ledger.lock().unwrap().reserved
+    }
+
+    /// What can still be reserved in the current window
+    pub fn remaining(&self) -> Amount {
+        let ledger = self.ledger_now();
+        self.limit.saturating_sub(ledger.spent()).saturating_sub(ledger.reserved)
+    }
+
+    /// Hold `amount` for a payment about to be submitted. Dropping the
+    /// reservation without [`Reservation::commit`] releases it.
+    pub fn reserve(&self, amount: Amount) -> Result<Reservation<'_>, BudgetExceeded> {
+        let mut ledger = self.ledger_now();
+        let remaining = self.limit.saturating_sub(ledger.spent()).saturating_sub(ledger.reserved);
+        if amount > remaining {
+            return Err(BudgetExceeded { spend: amount, remaining });
+        }
+
+        ledger.reserved = ledger.reserved.saturating_add(amount);
+        Ok(Reservation {
+            budget: self,
+            amount,
+            committed: false,
+        })
+    }
+
+    /// Credit part of a committed payment back, e.g. a stream refund. Spend
+    /// that has already left the window is not affected.
+    pub fn refund(&self, spend: SpendId, amount: Amount) {
+        let mut ledger = self.ledger_now();
+        if let Some((_, _, spent)) = ledger.spent.iter_mut().find(|(id, _, _)| *id == spend) {
+            *spent = spent.saturating_sub(amount);
+        }
+    }
+
+    fn ledger_now(&self) -> std::sync::MutexGuard<'_, Ledger> {
+        let mut ledger = self.ledger.lock().unwrap();
+        ledger.prune(self.window.start((self.clock)()));
+        ledger
+    }
+
+    /// Move `held` out of the reserved total and record `spent`
+    fn record(&self, held: Amount, spent: Amount) -> SpendId {
+        let now = (self.clock)();
+        let mut ledger = self.ledger.lock().unwrap();
+        let id = SpendId(ledger.next_id);
+        ledger.next_id += 1;
+        ledger.reserved = ledger.reserved.saturating_sub(held);
+        ledger.spent.push_back((id, now, spent));
+        id
+    }
+
+    fn release(&self, amount: Amount) {
+        let mut ledger = self.ledger.lock().unwrap();
+        ledger.reserved = ledger.reserved.saturating_sub(amount);
+    }
+}
+
+/// Budget held for one in-flight payment
+#[derive(Debug)]
+#[must_use = "dropping a reservation releases it"]
+pub struct Reservation<'a> {
+    budget: &'a Budget,
+    amount: Amount,
+    committed: bool,
+}
+
+impl Reservation<'_> {
+    pub fn amount(&self) -> Amount {
+        self.amount
+    }
+
+    /// Record the payment as made, converting the hold into spend
+    pub fn commit(self) -> SpendId {
+        let amount = self.amount;
+        self.commit_with(amount)
+    }
+
+    /// Record the payment as made for `spent` rather than the amount held,
+    /// e.g. when the gas actually paid came in under the estimate
+    pub fn commit_with(mut self, spent: Amount) -> SpendId {
+        let id = self.budget.record(self.amount, spent);
+        self.committed = true;
+        id
+    }
+
+    /// Turn `spent` of the hold into spend now, keeping the rest held. For
+    /// costs paid whether or not the payment itself goes through, such as
+    /// the gas of a transfer funding the paying address.
+    pub fn commit_part(&mut self, spent: Amount) -> SpendId {
+        let held = spent.min(self.amount);
+        self.amount = self.amount.saturating_sub(held);
+        self.budget.record(held, spent)
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        if !self.committed {
+            self.budget.release(self.amount);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicI64, Ordering};
+    use std::sync::Arc;
+
+    fn tcro(value: &str) -> Amount {
+        value.parse().unwrap()
+    }
+
+    /// Budget on a clock the test moves, starting at `start`
+    fn budget(limit: &str, window: BudgetWindow, start: DateTime<Utc>) -> (Budget, Arc<AtomicI64>) {
+        let now = Arc::new(AtomicI64::new(start.timestamp()));
+        let clock = now.clone();
+        let budget = Budget::new(tcro(limit), window)
+            .with_clock(move || DateTime::from_timestamp(clock.load(Ordering::Relaxed), 0).unwrap());
+        (budget, now)
+    }
+
+    fn at(rfc3339: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(rfc3339).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn reservations_hold_until_committed_or_dropped() {
+        let (budget, _) = budget("1", BudgetWindow::default(), at("2026-03-01T12:00:00Z"));
+
+        let held = budget.reserve(tcro("0.6")).unwrap();
+        assert_eq!(budget.remaining(), tcro("0.4"));
+        assert_eq!(
+            budget.reserve(tcro("0.5")).unwrap_err(),
+            BudgetExceeded {
+                spend: tcro("0.5"),
+                remaining: tcro("0.4"),
+            }
+        );
+        drop(held);
+        assert_eq!((budget.reserved(), budget.remaining()), (Amount::ZERO, tcro("1")));
+
+        budget.reserve(tcro("0.6")).unwrap().commit();
+        assert_eq!((budget.reserved(), budget.spent()), (Amount::ZERO, tcro("0.6")));
+
+        // Gas came in under the estimate held for it
+        budget.reserve(tcro("0.3")).unwrap().commit_with(tcro("0.25"));
+        assert_eq!(budget.spent(), tcro("0.85"));
+        assert_eq!(budget.remaining(), tcro("0.15"));
+    }
+
+    #[test]
+    fn committed_parts_stay_spent_when_the_rest_is_released() {
+        let (budget, _) = budget("1", BudgetWindow::default(), at("2026-03-01T12:00:00Z"));
+
+        let mut held = budget.reserve(tcro("0.5")).unwrap();
+        held.commit_part(tcro("0.01"));
+        assert_eq!((budget.reserved(), budget.spent()), (tcro("0.49"), tcro("0.01")));
+        drop(held);
+        assert_eq!((budget.reserved(), budget.remaining()), (Amount::ZERO, tcro("0.99")));
+    }
+
+    #[test]
+    fn refunds_credit_the_committed_spend() {
+        let (budget, _) = budget("1", BudgetWindow::default(), at("2026-03-01T12:00:00Z"));
+
+        let stream = budget.reserve(tcro("0.36")).unwrap().commit();
+        let other = budget.reserve(tcro("0.1")).unwrap().commit();
+        budget.refund(stream, tcro("0.3"));
+        budget.refund(other, tcro("1"));
+
+        assert_eq!(budget.spent(), tcro("0.06"));
+        assert_eq!(budget.remaining(), tcro("0.94"));
+    }
+
+    #[test]
+    fn calendar_day_resets_at_midnight_in_its_time_zone() {
+        // Midnight in Tokyo is 15:00 UTC
+        let (budget, now) = budget("1", BudgetWindow::CalendarDay(Tz::Asia__Tokyo), at("2026-03-01T14:00:00Z"));
+
+        budget.reserve(tcro("0.8")).unwrap().commit();
+        now.store(at("2026-03-01T14:59:59Z").timestamp(), Ordering::Relaxed);
+        assert_eq!(budget.remaining(), tcro("0.2"));
+
+        now.store(at("2026-03-01T15:00:00Z").timestamp(), Ordering::Relaxed);
+        assert_eq!(budget.spent(), Amount::ZERO);
+        assert_eq!(budget.remaining(), tcro("1"));
+    }
+
+    #[test]
+    fn rolling_window_expires_each_spend_after_24_hours() {
+        let start = at("2026-03-01T12:00:00Z");
+        let (budget, now) = budget("1", BudgetWindow::Rolling24h, start);
+
+        budget.reserve(tcro("0.5")).unwrap().commit();
+        now.store((start + Duration::hours(6)).timestamp(), Ordering::Relaxed);
+        budget.reserve(tcro("0.3")).unwrap().commit();
+
+        // Midnight does not reset a rolling window
+        now.store((start + Duration::hours(24)).timestamp(), Ordering::Relaxed);
+        assert_eq!(budget.spent(), tcro("0.8"));
+
+        now.store((start + Duration::hours(24) + Duration::seconds(1)).timestamp(), Ordering::Relaxed);
+        assert_eq!(budget.spent(), tcro("0.3"));
+        now.store((start + Duration::hours(30) + Duration::seconds(1)).timestamp(), Ordering::Relaxed);
+        assert_eq!(budget.spent(), Amount::ZERO);
+    }
+}
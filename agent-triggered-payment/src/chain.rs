@@ -0,0 +1,511 @@
+use async_trait::async_trait;
+use ethers::contract::{abigen, parse_log};
+use ethers::middleware::SignerMiddleware;
+use ethers::providers::{Http, Middleware, Provider, ProviderError};
+use ethers::signers::{LocalWallet, Signer};
+use ethers::types::{TransactionReceipt, TransactionRequest, H256, U256};
+use std::sync::Arc;
+use thiserror::Error;
+use tracing::info;
+
+use crate::address::Address;
+use crate::amount::Amount;
+use crate::backend::{LedgerTransfer, PaymentBackend, StreamState};
+use crate::sizing::{self, SizingError};
+use crate::wallet::{AgentWallet, HdWallet};
+
+abigen!(
+    PayStreamStream,
+    r#"[
+        function createStream(address recipient, uint256 duration, string metadata) external payable
+        function cancelStream(uint256 streamId) external
+        function isStreamActive(uint256 streamId) external view returns (bool)
+        function getClaimableBalance(uint256 streamId) external view returns (uint256)
+        function streams(uint256 streamId) external view returns (address sender, address recipient, uint256 totalAmount, uint256 flowRate, uint256 startTime, uint256 stopTime, uint256 amountWithdrawn, bool isActive, string metadata)
+        event StreamCreated(uint256 indexed streamId, address indexed sender, address indexed recipient, uint256 totalAmount, uint256 startTime, uint256 stopTime, string metadata)
+        event StreamCancelled(uint256 indexed streamId, address sender, address recipient, uint256 senderBalance, uint256 recipientBalance)
+    ]"#
+);
+
+/// Gas limit of a plain native transfer
+pub const TRANSFER_GAS: u64 = 21_000;
+/// Generous gas limit for `createStream`, which stores the metadata string
+pub const CREATE_STREAM_GAS: u64 = 500_000;
+
+/// Most a transaction with `gas_limit` can cost if the gas price doubles
+/// before it is mined, the headroom [`PayStreamChain::fund`] allows for
+pub fn max_fee(gas_price: Amount, gas_limit: u64) -> Amount {
+    Amount::from_wei(gas_price.wei().saturating_mul(U256::from(gas_limit) * 2))
+}
+
+/// Provider with a local signing wallet attached
+pub type SignerClient = SignerMiddleware<Provider<Http>, LocalWallet>;
+
+#[derive(Error, Debug)]
+pub enum ChainError {
+    #[error("Invalid chain configuration: {0}")]
+    Config(String),
+    #[error("RPC request failed: {0}")]
+    Rpc(#[from] ProviderError),
+    #[error("Contract call failed: {0}")]
+    Contract(String),
+    #[error("Could not send transaction: {0}")]
+    Send(String),
+    #[error("Transaction {0:?} was dropped from the mempool")]
+    Dropped(H256),
+    #[error("Transaction {0:?} reverted")]
+    Reverted(H256),
+    #[error("Stream #{0} is not open")]
+    UnknownStream(u64),
+    /// Arguments `createStream` would revert on, caught before sending
+    #[error("Invalid stream: {0}")]
+    InvalidStream(#[from] SizingError),
+    #[error("Transaction {tx_hash:?} emitted no {event} event")]
+    MissingEvent { tx_hash: H256, event: &'static str },
+}
+
+/// Where and how to reach the `PayStreamStream` contract
+#[derive(Debug, Clone)]
+pub struct ChainConfig {
+    pub rpc_url: String,
+    pub contract: Address,
+    /// Blocks to wait for, including the one that mined a transaction,
+    /// before treating it as settled
+    pub confirmations: usize,
+}
+
+impl ChainConfig {
+    /// Read `CRONOS_RPC_URL`, `PAYSTREAM_CONTRACT` (or the older
+    /// `FLOWPAY_CONTRACT`) and `PAYSTREAM_CONFIRMATIONS` (default 1)
+    pub fn from_env() -> Result<Self, ChainError> {
+        let rpc_url = std::env::var("CRONOS_RPC_URL")
+            .unwrap_or_else(|_| "https://evm-t3.cronos.org".to_string());
+        let contract = std::env::var("PAYSTREAM_CONTRACT")
+            .or_else(|_| std::env::var("FLOWPAY_CONTRACT"))
+            .map_err(|_| ChainError::Config("PAYSTREAM_CONTRACT is not set".to_string()))?
+            .parse()
+            .map_err(|e| ChainError::Config(format!("PAYSTREAM_CONTRACT: {}", e)))?;
+        let confirmations = match std::env::var("PAYSTREAM_CONFIRMATIONS") {
+            Ok(value) => value
+                .parse()
+                .map_err(|e| ChainError::Config(format!("PAYSTREAM_CONFIRMATIONS: {}", e)))?,
+            Err(_) => 1,
+        };
+
+        Ok(Self { rpc_url, contract, confirmations })
+    }
+}
+
+/// A stream opened on chain, as reported by its `StreamCreated` event
+#[derive(Debug, Clone)]
+pub struct CreatedStream {
+    pub stream_id: u64,
+    pub tx_hash: H256,
+    pub block_number: Option<u64>,
+    pub total_amount: Amount,
+    pub start_time: u64,
+    pub stop_time: u64,
+    pub gas_cost: Amount,
+}
+
+/// A stream closed by `cancelStream`, as reported by its `StreamCancelled`
+/// event
+#[derive(Debug, Clone)]
+pub struct CancelledStream {
+    pub stream_id: u64,
+    pub tx_hash: H256,
+    pub block_number: Option<u64>,
+    /// Unstreamed deposit returned to the sender
+    pub refund: Amount,
+    /// Streamed amount not yet withdrawn, paid out to the recipient
+    pub payout: Amount,
+    pub gas_cost: Amount,
+}
+
+/// A confirmed native token transfer
+#[derive(Debug, Clone)]
+pub struct Transfer {
+    pub tx_hash: H256,
+    pub block_number: Option<u64>,
+    pub amount: Amount,
+    /// Gas used times the effective gas price
+    pub gas_cost: Amount,
+}
+
+/// Signed access to a deployed `PayStreamStream` contract over JSON-RPC
+pub struct PayStreamChain {
+    client: Arc<SignerClient>,
+    contract: PayStreamStream<SignerClient>,
+    confirmations: usize,
+}
+
+impl PayStreamChain {
+    /// Connect to the RPC endpoint and bind the wallet to its chain ID
+    pub async fn connect(config: &ChainConfig, wallet: &AgentWallet) -> Result<Self, ChainError> {
+        let provider = Provider::<Http>::try_from(config.rpc_url.as_str())
+            .map_err(|e| ChainError::Config(format!("RPC URL {}: {}", config.rpc_url, e)))?;
+        let chain_id = provider.get_chainid().await?.as_u64();
+
+        let client = Arc::new(SignerMiddleware::new(provider, wallet.signer().clone().with_chain_id(chain_id)));
+        let contract = PayStreamStream::new(config.contract.as_h160(), client.clone());
+
+        Ok(Self {
+            client,
+            contract,
+            confirmations: config.confirmations.max(1),
+        })
+    }
+
+    /// Same endpoint and contract, signing with another wallet
+    pub fn with_wallet(&self, wallet: &AgentWallet) -> Self {
+        let chain_id = self.client.signer().chain_id();
+        let client = Arc::new(self.client.with_signer(wallet.signer().clone().with_chain_id(chain_id)));
+        let contract = PayStreamStream::new(self.contract.address(), client.clone());
+        Self {
+            client,
+            contract,
+            confirmations: self.confirmations,
+        }
+    }
+
+    /// Address payments are sent from
+    pub fn sender(&self) -> Address {
+        Address::from_h160(self.client.address())
+    }
+
+    /// Native token balance of an account
+    pub async fn balance(&self, account: Address) -> Result<Amount, ChainError> {
+        let balance = self.client.inner().get_balance(account.as_h160(), None).await?;
+        Ok(Amount::from_wei(balance))
+    }
+
+    /// Current gas price
+    pub async fn gas_price(&self) -> Result<Amount, ChainError> {
+        Ok(Amount::from_wei(self.client.inner().get_gas_price().await?))
+    }
+
+    /// Top `account` up so it can send `value` plus `gas_limit` gas at twice
+    /// the current gas price. Returns the funding transfer, if one was needed.
+    pub async fn fund(&self, account: Address, value: Amount, gas_limit: u64) -> Result<Option<Transfer>, ChainError> {
+        let needed = value.saturating_add(max_fee(self.gas_price().await?, gas_limit));
+
+        let balance = self.balance(account).await?;
+        if balance >= needed {
+            return Ok(None);
+        }
+        info!("   ├─ Funding {} with {} TCRO", account.short(), needed.saturating_sub(balance));
+        self.transfer(account, needed.saturating_sub(balance)).await.map(Some)
+    }
+
+    /// Send this wallet's whole balance, less gas, to `to`. Returns `None`
+    /// when the balance does not cover the gas.
+    pub async fn sweep(&self, to: Address) -> Result<Option<Transfer>, ChainError> {
+        let gas_price = self.client.inner().get_gas_price().await?;
+        let fee = Amount::from_wei(gas_price.saturating_mul(TRANSFER_GAS.into()));
+        let balance = self.balance(self.sender()).await?;
+        if balance <= fee {
+            return Ok(None);
+        }
+
+        // Legacy pricing so the fee is exact and nothing is left behind
+        let tx = TransactionRequest::new()
+            .to(to.as_h160())
+            .value(balance.saturating_sub(fee).wei())
+            .gas(TRANSFER_GAS)
+            .gas_price(gas_price);
+        self.send_transfer(tx, balance.saturating_sub(fee)).await.map(Some)
+    }
+
+    /// Sweep the per-service children of HD account `account` into this
+    /// wallet. Children are scanned in order until `gap_limit` consecutive
+    /// ones have never been used.
+    pub async fn sweep_hd_children(
+        &self,
+        hd: &HdWallet,
+        account: u32,
+        gap_limit: u32,
+    ) -> Result<Vec<Transfer>, ChainError> {
+        let mut swept = Vec::new();
+        let mut unused = 0;
+        let mut child = 1;
+
+        while unused < gap_limit {
+            let wallet = hd
+                .service_child(account, child)
+                .map_err(|e| ChainError::Config(e.to_string()))?;
+            let address = wallet.address();
+            let used = !self.client.inner().get_transaction_count(address.as_h160(), None).await?.is_zero()
+                || !self.balance(address).await?.is_zero();
+
+            if used {
+                unused = 0;
+                if let Some(transfer) = self.with_wallet(&wallet).sweep(self.sender()).await? {
+                    info!("🧹 Swept {} TCRO from {}", transfer.amount, address.short());
+                    swept.push(transfer);
+                }
+            } else {
+                unused += 1;
+            }
+            child += 1;
+        }
+
+        Ok(swept)
+    }
+
+    /// Call `createStream(recipient, duration, metadata)` with the deposit as
+    /// `msg.value` and read the stream ID from the `StreamCreated` event
+    pub async fn create_stream(
+        &self,
+        recipient: Address,
+        duration_secs: u64,
+        deposit: Amount,
+        metadata: &str,
+    ) -> Result<CreatedStream, ChainError> {
+        sizing::validate(recipient, deposit, duration_secs, None)?;
+        let call = self
+            .contract
+            .create_stream(recipient.as_h160(), U256::from(duration_secs), metadata.to_string())
+            .value(deposit.wei());
+        let pending = call
+            .send()
+            .await
+            .map_err(|e| ChainError::Contract(e.to_string()))?;
+        let tx_hash = *pending;
+        info!("   ├─ Submitted createStream: {:?}", tx_hash);
+
+        let receipt = pending
+            .confirmations(self.confirmations)
+            .await?
+            .ok_or(ChainError::Dropped(tx_hash))?;
+        Self::ensure_success(&receipt)?;
+
+        let event = receipt
+            .logs
+            .iter()
+            .filter(|log| log.address == self.contract.address())
+            .find_map(|log| parse_log::<StreamCreatedFilter>(log.clone()).ok())
+            .ok_or(ChainError::MissingEvent {
+                tx_hash,
+                event: "StreamCreated",
+            })?;
+
+        Ok(CreatedStream {
+            stream_id: event.stream_id.as_u64(),
+            tx_hash,
+            block_number: receipt.block_number.map(|b| b.as_u64()),
+            total_amount: Amount::from_wei(event.total_amount),
+            start_time: event.start_time.as_u64(),
+            stop_time: event.stop_time.as_u64(),
+            gas_cost: Self::gas_cost(&receipt),
+        })
+    }
+
+    /// Call `cancelStream(streamId)` and read the refund and payout from the
+    /// `StreamCancelled` event. Only the stream's sender or recipient may
+    /// cancel it.
+    pub async fn cancel_stream(&self, stream_id: u64) -> Result<CancelledStream, ChainError> {
+        let call = self.contract.cancel_stream(U256::from(stream_id));
+        let pending = call
+            .send()
+            .await
+            .map_err(|e| ChainError::Contract(e.to_string()))?;
+        let tx_hash = *pending;
+        info!("   ├─ Submitted cancelStream: {:?}", tx_hash);
+
+        let receipt = pending
+            .confirmations(self.confirmations)
+            .await?
+            .ok_or(ChainError::Dropped(tx_hash))?;
+        Self::ensure_success(&receipt)?;
+
+        let event = receipt
+            .logs
+            .iter()
+            .filter(|log| log.address == self.contract.address())
+            .find_map(|log| parse_log::<StreamCancelledFilter>(log.clone()).ok())
+            .ok_or(ChainError::MissingEvent {
+                tx_hash,
+                event: "StreamCancelled",
+            })?;
+
+        Ok(CancelledStream {
+            stream_id,
+            tx_hash,
+            block_number: receipt.block_number.map(|b| b.as_u64()),
+            refund: Amount::from_wei(event.sender_balance),
+            payout: Amount::from_wei(event.recipient_balance),
+            gas_cost: Self::gas_cost(&receipt),
+        })
+    }
+
+    /// Read `streams(id)`, plus `getClaimableBalance(id)` while the stream
+    /// is active
+    pub async fn stream(&self, stream_id: u64) -> Result<StreamState, ChainError> {
+        let id = U256::from(stream_id);
+        let (sender, recipient, total_amount, flow_rate, start_time, stop_time, amount_withdrawn, is_active, metadata) = self
+            .contract
+            .streams(id)
+            .call()
+            .await
+            .map_err(|e| ChainError::Contract(e.to_string()))?;
+        if sender.is_zero() {
+            return Err(ChainError::UnknownStream(stream_id));
+        }
+        let claimable = if is_active {
+            self.contract
+                .get_claimable_balance(id)
+                .call()
+                .await
+                .map_err(|e| ChainError::Contract(e.to_string()))?
+        } else {
+            U256::zero()
+        };
+
+        Ok(StreamState {
+            stream_id,
+            sender: Address::from_h160(sender),
+            recipient: Address::from_h160(recipient),
+            total_amount: Amount::from_wei(total_amount),
+            flow_rate: Amount::from_wei(flow_rate),
+            start_time: start_time.as_u64(),
+            stop_time: stop_time.as_u64(),
+            amount_withdrawn: Amount::from_wei(amount_withdrawn),
+            is_active,
+            claimable: Amount::from_wei(claimable),
+            metadata,
+        })
+    }
+
+    /// Look up a successful native transfer by hash. Transactions that are
+    /// pending, short of the configured confirmations, reverted, or that
+    /// call a contract or carry calldata give `None`.
+    pub async fn find_transfer(&self, tx_hash: H256) -> Result<Option<LedgerTransfer>, ChainError> {
+        let provider = self.client.inner();
+        let Some(tx) = provider.get_transaction(tx_hash).await? else {
+            return Ok(None);
+        };
+        let (Some(to), Some(block_number)) = (tx.to, tx.block_number) else {
+            return Ok(None);
+        };
+        if !tx.input.is_empty() {
+            return Ok(None);
+        }
+
+        let latest = provider.get_block_number().await?;
+        if latest.as_u64() + 1 < block_number.as_u64() + self.confirmations as u64 {
+            return Ok(None);
+        }
+        let succeeded = provider
+            .get_transaction_receipt(tx_hash)
+            .await?
+            .is_some_and(|receipt| Self::ensure_success(&receipt).is_ok());
+        if !succeeded {
+            return Ok(None);
+        }
+
+        Ok(Some(LedgerTransfer {
+            tx_hash,
+            block_number: block_number.as_u64(),
+            from: Address::from_h160(tx.from),
+            to: Address::from_h160(to),
+            amount: Amount::from_wei(tx.value),
+        }))
+    }
+
+    /// Send `amount` of the native token to `recipient` and wait for the
+    /// configured number of confirmations
+    pub async fn transfer(&self, recipient: Address, amount: Amount) -> Result<Transfer, ChainError> {
+        let tx = TransactionRequest::new().to(recipient.as_h160()).value(amount.wei());
+        self.send_transfer(tx, amount).await
+    }
+
+    async fn send_transfer(&self, tx: TransactionRequest, amount: Amount) -> Result<Transfer, ChainError> {
+        let pending = self
+            .client
+            .send_transaction(tx, None)
+            .await
+            .map_err(|e| ChainError::Send(e.to_string()))?;
+        let tx_hash = *pending;
+        info!("   ├─ Submitted transfer: {:?}", tx_hash);
+
+        let receipt = pending
+            .confirmations(self.confirmations)
+            .await?
+            .ok_or(ChainError::Dropped(tx_hash))?;
+        Self::ensure_success(&receipt)?;
+
+        Ok(Transfer {
+            tx_hash,
+            block_number: receipt.block_number.map(|b| b.as_u64()),
+            amount,
+            gas_cost: Self::gas_cost(&receipt),
+        })
+    }
+
+    fn gas_cost(receipt: &TransactionReceipt) -> Amount {
+        let gas_used = receipt.gas_used.unwrap_or_default();
+        let price = receipt.effective_gas_price.unwrap_or_default();
+        Amount::from_wei(gas_used.saturating_mul(price))
+    }
+
+    fn ensure_success(receipt: &TransactionReceipt) -> Result<(), ChainError> {
+        if receipt.status == Some(1u64.into()) {
+            Ok(())
+        } else {
+            Err(ChainError::Reverted(receipt.transaction_hash))
+        }
+    }
+}
+
+#[async_trait]
+impl PaymentBackend for PayStreamChain {
+    fn sender(&self) -> Address {
+        PayStreamChain::sender(self)
+    }
+
+    fn with_wallet(&self, wallet: &AgentWallet) -> Self {
+        PayStreamChain::with_wallet(self, wallet)
+    }
+
+    async fn balance(&self, account: Address) -> Result<Amount, ChainError> {
+        PayStreamChain::balance(self, account).await
+    }
+
+    async fn gas_price(&self) -> Result<Amount, ChainError> {
+        PayStreamChain::gas_price(self).await
+    }
+
+    async fn open_stream(
+        &self,
+        recipient: Address,
+        duration_secs: u64,
+        deposit: Amount,
+        metadata: &str,
+    ) -> Result<CreatedStream, ChainError> {
+        self.create_stream(recipient, duration_secs, deposit, metadata).await
+    }
+
+    async fn pay(&self, recipient: Address, amount: Amount) -> Result<Transfer, ChainError> {
+        self.transfer(recipient, amount).await
+    }
+
+    async fn cancel_stream(&self, stream_id: u64) -> Result<CancelledStream, ChainError> {
+        PayStreamChain::cancel_stream(self, stream_id).await
+    }
+
+    async fn stream(&self, stream_id: u64) -> Result<StreamState, ChainError> {
+        PayStreamChain::stream(self, stream_id).await
+    }
+
+    async fn find_transfer(&self, tx_hash: H256) -> Result<Option<LedgerTransfer>, ChainError> {
+        PayStreamChain::find_transfer(self, tx_hash).await
+    }
+
+    async fn fund(&self, account: Address, value: Amount, gas_limit: u64) -> Result<Option<Transfer>, ChainError> {
+        PayStreamChain::fund(self, account, value, gas_limit).await
+    }
+
+    async fn sweep(&self, to: Address) -> Result<Option<Transfer>, ChainError> {
+        PayStreamChain::sweep(self, to).await
+    }
+}
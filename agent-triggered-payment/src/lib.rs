@@ -0,0 +1,17 @@
+pub mod address;
+pub mod amount;
+pub mod backend;
+pub mod budget;
+pub mod chain;
+pub mod gemini;
+pub mod negotiation;
+pub mod payment_agent;
+#[cfg(feature = "paywall")]
+pub mod paywall;
+pub mod sizing;
+pub mod streams;
+#[cfg(feature = "test-support")]
+pub mod test_support;
+pub mod wallet;
+pub mod x402;
+pub mod x402_spec;
@@ -1,15 +1,26 @@
-mod gemini;
-mod payment_agent;
-mod x402;
-
 use std::sync::Arc;
+use std::time::Duration;
 use dotenv::dotenv;
 use tracing::{info, Level};
 use tracing_subscriber::FmtSubscriber;
 
-use gemini::GeminiClient;
-use payment_agent::{PaymentAgent, AgentConfig};
-use x402::{X402PaymentRequirement, PaymentMode};
+use paystream_cro::address::Address;
+use paystream_cro::amount::Amount;
+use paystream_cro::gemini::GeminiClient;
+use paystream_cro::backend::MemoryBackend;
+use paystream_cro::payment_agent::{PaymentAgent, AgentConfig, SIMULATED_BALANCE};
+use paystream_cro::wallet::{AgentWallet, HdWallet, MNEMONIC_ENV, PRIVATE_KEY_ENV};
+use paystream_cro::x402::{X402PaymentRequirement, PaymentMode, HeaderDialect};
+
+/// Parse a literal address used in the demo scenarios
+fn addr(value: &str) -> Address {
+    value.parse().expect("valid address")
+}
+
+/// Parse a literal TCRO amount used in the demo scenarios
+fn tcro(value: &str) -> Amount {
+    value.parse().expect("valid TCRO amount")
+}
 
 #[tokio::main]
 async fn main() {
@@ -32,31 +43,56 @@ async fn main() {
     
     let gemini = Arc::new(GeminiClient::new(api_key));
 
+    // One simulated ledger for the whole fleet
+    let ledger = MemoryBackend::new(Address::ZERO).with_opening_balance(SIMULATED_BALANCE);
+    let simulated = |config: AgentConfig| {
+        let address = config.wallet_address;
+        PaymentAgent::new(config, gemini.clone()).with_backend(ledger.for_sender(address))
+    };
+
     // Create payment agents
-    let agents = vec![
-        PaymentAgent::new(
-            AgentConfig {
-                name: "weather-bot".to_string(),
-                wallet_address: "0xABCD1234567890ABCD1234567890ABCD12345678".to_string(),
-                daily_budget: 50.0,
-            },
-            gemini.clone(),
-        ),
-        PaymentAgent::new(
-            AgentConfig {
-                name: "data-collector".to_string(),
-                wallet_address: "0xEFGH9876543210EFGH9876543210EFGH98765432".to_string(),
-                daily_budget: 100.0,
-            },
-            gemini.clone(),
-        ),
+    let mut agents = vec![
+        simulated(AgentConfig {
+            name: "weather-bot".to_string(),
+            wallet_address: addr("0xabcd1234567890abcd1234567890abcd12345678"),
+            daily_budget: Amount::from_whole(50),
+            hd_index: Some(0),
+            idle_stream_timeout: Some(Duration::from_secs(300)),
+            ..AgentConfig::default()
+        }),
+        simulated(AgentConfig {
+            name: "data-collector".to_string(),
+            wallet_address: addr("0xef009876543210ef009876543210ef0098765432"),
+            daily_budget: Amount::from_whole(100),
+            hd_index: Some(1),
+            fresh_address_per_service: true,
+            ..AgentConfig::default()
+        }),
     ];
 
+    // Sign with real keys when configured: one mnemonic for the whole fleet,
+    // or a single key for the first agent
+    match HdWallet::from_env(MNEMONIC_ENV) {
+        Ok(hd) => {
+            agents = agents
+                .into_iter()
+                .map(|agent| agent.with_hd_wallet(hd.clone()).expect("derive agent key"))
+                .collect();
+        }
+        Err(_) => match AgentWallet::from_env(PRIVATE_KEY_ENV) {
+            Ok(wallet) => {
+                let first = agents.remove(0).with_wallet(wallet);
+                agents.insert(0, first);
+            }
+            Err(_) => info!("ℹ️  Neither {} nor {} is set; using demo addresses", MNEMONIC_ENV, PRIVATE_KEY_ENV),
+        },
+    }
+
     // Display initialized agents
     for agent in &agents {
         info!("🤖 Agent {} initialized", agent.id);
-        info!("   ├─ Wallet: {}...{}", &agent.config.wallet_address[..6], &agent.config.wallet_address[38..]);
-        info!("   └─ Budget: {:.2} TCRO", agent.config.daily_budget);
+        info!("   ├─ Wallet: {}", agent.config.wallet_address.short());
+        info!("   └─ Budget: {}", agent.config.daily_budget.with_symbol("TCRO"));
         println!();
     }
 
@@ -67,14 +103,17 @@ async fn main() {
             0, // Agent index
             "https://api.weather-service.com/forecast",
             X402PaymentRequirement {
-                recipient: "0x5678EFGH9012IJKL5678EFGH9012IJKL56789012".to_string(),
+                recipient: addr("0x5678ef009012abcd5678ef009012abcd56789012"),
                 amount: None,
                 mode: PaymentMode::Streaming,
-                rate_per_second: Some("0.0001".to_string()),
-                min_deposit: Some("1.00".to_string()),
+                rate_per_second: Some(tcro("0.0001")),
+                min_deposit: Some(tcro("1.00")),
                 description: Some("Real-time weather data API".to_string()),
                 network: Some("cronos_testnet".to_string()),
                 token: Some("TCRO".to_string()),
+                contract: None,
+                scheme: None,
+                dialect: HeaderDialect::FlowPay,
             },
         ),
         // Scenario 2: Translation API (Per-request mode)
@@ -82,14 +121,17 @@ async fn main() {
             0,
             "https://api.translate-agent.com/translate",
             X402PaymentRequirement {
-                recipient: "0xAAAABBBBCCCCDDDD1111222233334444AAAABBBB".to_string(),
-                amount: Some("0.005".to_string()),
+                recipient: addr("0xaaaabbbbccccdddd1111222233334444aaaabbbb"),
+                amount: Some(tcro("0.005")),
                 mode: PaymentMode::PerRequest,
                 rate_per_second: None,
                 min_deposit: None,
                 description: Some("AI Translation Service".to_string()),
                 network: Some("cronos_testnet".to_string()),
                 token: Some("TCRO".to_string()),
+                contract: None,
+                scheme: None,
+                dialect: HeaderDialect::FlowPay,
             },
         ),
         // Scenario 3: Compute API (Streaming, different agent)
@@ -97,14 +139,17 @@ async fn main() {
             1,
             "https://gpu.compute-cloud.io/v1/inference",
             X402PaymentRequirement {
-                recipient: "0x1111222233334444555566667777888899990000".to_string(),
+                recipient: addr("0x1111222233334444555566667777888899990000"),
                 amount: None,
                 mode: PaymentMode::Streaming,
-                rate_per_second: Some("0.01".to_string()),
-                min_deposit: Some("5.00".to_string()),
+                rate_per_second: Some(tcro("0.01")),
+                min_deposit: Some(tcro("5.00")),
                 description: Some("GPU Compute - ML Inference".to_string()),
                 network: Some("cronos_testnet".to_string()),
                 token: Some("TCRO".to_string()),
+                contract: None,
+                scheme: None,
+                dialect: HeaderDialect::FlowPay,
             },
         ),
         // Scenario 4: Data feed (Per-request)
@@ -112,14 +157,35 @@ async fn main() {
             1,
             "https://api.market-data.io/prices",
             X402PaymentRequirement {
-                recipient: "0xDATAFEED00001111222233334444555566667777".to_string(),
-                amount: Some("0.001".to_string()),
+                recipient: addr("0xda7afeed00001111222233334444555566667777"),
+                amount: Some(tcro("0.001")),
                 mode: PaymentMode::PerRequest,
                 rate_per_second: None,
                 min_deposit: None,
                 description: Some("Real-time market price feed".to_string()),
                 network: Some("cronos_testnet".to_string()),
                 token: Some("TCRO".to_string()),
+                contract: None,
+                scheme: None,
+                dialect: HeaderDialect::FlowPay,
+            },
+        ),
+        // Scenario 5: Weather API again (reuses the stream from scenario 1)
+        (
+            0,
+            "https://api.weather-service.com/current",
+            X402PaymentRequirement {
+                recipient: addr("0x5678ef009012abcd5678ef009012abcd56789012"),
+                amount: None,
+                mode: PaymentMode::Streaming,
+                rate_per_second: Some(tcro("0.0001")),
+                min_deposit: Some(tcro("1.00")),
+                description: Some("Real-time weather data API".to_string()),
+                network: Some("cronos_testnet".to_string()),
+                token: Some("TCRO".to_string()),
+                contract: None,
+                scheme: None,
+                dialect: HeaderDialect::FlowPay,
             },
         ),
     ];
@@ -145,9 +211,12 @@ async fn main() {
                     if let Some(stream_id) = result.stream_id {
                         info!("   Stream ID: #{}", stream_id);
                     }
-                    if let Some(ref amount) = result.amount_spent {
-                        info!("   Amount: {} TCRO", amount);
+                    if let Some(amount) = result.amount_spent {
+                        info!("   Amount: {}", amount.with_symbol("TCRO"));
                     }
+                } else if let Some(stream_id) = result.stream_id {
+                    info!("✅ HTTP {} - Served on open stream #{}", result.status, stream_id);
+                    info!("   Response: {}", result.body);
                 } else {
                     info!("✅ HTTP {} - No payment required", result.status);
                 }
@@ -165,6 +234,11 @@ async fn main() {
         tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
     }
 
+    // Close open streams so unstreamed deposits come back
+    for agent in &agents {
+        agent.cancel_all_streams().await;
+    }
+
     // Display final stats
     println!();
     info!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
@@ -187,5 +261,6 @@ async fn main() {
     info!("   4. Created TCRO payment streams or made per-request payments");
     info!("   5. Retried requests with payment proof");
     info!("   6. Successfully accessed paid services");
+    info!("   7. Cancelled open streams and reclaimed the unused deposits");
     println!();
 }
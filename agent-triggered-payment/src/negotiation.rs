@@ -0,0 +1,339 @@
+//! Choosing among the payment options a 402 offers, by expected cost for the
+//! caller's workload, an allow-list policy and the budget headroom.
+
+use serde::{Deserialize, Serialize};
+
+use crate::amount::{Amount, SYMBOL};
+use crate::x402::{PaymentMode, X402PaymentRequirement, DEFAULT_AMOUNT, DEFAULT_DEPOSIT};
+
+/// Expected use of a service, used to price offers against each other
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Workload {
+    /// Requests the caller expects to make
+    pub requests: u64,
+    /// Seconds the caller expects to keep using the service
+    pub duration_secs: u64,
+}
+
+impl Default for Workload {
+    fn default() -> Self {
+        Self { requests: 1, duration_secs: 0 }
+    }
+}
+
+/// Which offers the caller is willing to take. Empty lists allow anything;
+/// offers that do not name a network or token are always allowed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OfferPolicy {
+    pub allowed_networks: Vec<String>,
+    pub allowed_tokens: Vec<String>,
+}
+
+impl OfferPolicy {
+    fn rejects(&self, offer: &X402PaymentRequirement) -> Option<String> {
+        let allowed = |list: &[String], value: &Option<String>| match value {
+            Some(value) if !list.is_empty() => list.iter().any(|v| v.eq_ignore_ascii_case(value)),
+            _ => true,
+        };
+
+        if !allowed(&self.allowed_networks, &offer.network) {
+            return Some(format!("network {} not allowed", offer.network.as_deref().unwrap_or_default()));
+        }
+        if !allowed(&self.allowed_tokens, &offer.token) {
+            return Some(format!("token {} not allowed", offer.token.as_deref().unwrap_or_default()));
+        }
+        None
+    }
+}
+
+/// The offer chosen for a 402 and why
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Negotiation {
+    pub chosen: X402PaymentRequirement,
+    /// Cost of the declared workload under the chosen offer
+    pub expected_cost: Amount,
+    /// Amount paid before the first retry
+    pub upfront_cost: Amount,
+    /// Most the gas for that payment can cost, reserved alongside it
+    pub max_fee: Amount,
+    pub offers_considered: usize,
+    pub rationale: String,
+}
+
+/// Amount paid before the first retry: the minimum deposit for a stream,
+/// as the agent opens it, or one call
+pub fn upfront_cost(offer: &X402PaymentRequirement) -> Amount {
+    match offer.mode {
+        PaymentMode::Streaming => offer.min_deposit.unwrap_or(DEFAULT_DEPOSIT),
+        PaymentMode::PerRequest => offer.amount.unwrap_or(DEFAULT_AMOUNT),
+    }
+}
+
+/// Cost of the workload under an offer. Streams cost the deposit or the
+/// streamed amount, whichever is larger, since the whole deposit is spent.
+pub fn expected_cost(offer: &X402PaymentRequirement, workload: &Workload) -> Amount {
+    match offer.mode {
+        PaymentMode::Streaming => {
+            let streamed = offer
+                .rate_per_second
+                .map(|rate| Amount::from_wei(rate.wei().saturating_mul(workload.duration_secs.into())))
+                .unwrap_or_default();
+            streamed.max(offer.min_deposit.unwrap_or(DEFAULT_DEPOSIT))
+        }
+        PaymentMode::PerRequest => {
+            let amount = offer.amount.unwrap_or(DEFAULT_AMOUNT);
+            Amount::from_wei(amount.wei().saturating_mul(workload.requests.max(1).into()))
+        }
+    }
+}
+
+fn describe(offer: &X402PaymentRequirement) -> String {
+    let symbol = offer.token.as_deref().unwrap_or("TCRO");
+    match offer.mode {
+        PaymentMode::Streaming => format!(
+            "streaming at {}/s",
+            offer.rate_per_second.unwrap_or_default().with_symbol(symbol)
+        ),
+        PaymentMode::PerRequest => format!(
+            "per-request at {}",
+            offer.amount.unwrap_or(DEFAULT_AMOUNT).with_symbol(symbol)
+        ),
+    }
+}
+
+/// Pick the cheapest offer for the workload that the policy allows and the
+/// budget headroom can cover up front, together with `max_fee` for the
+/// offer's gas. The budget and payments are in the
+/// native token, so only offers priced in [`SYMBOL`], or naming no token,
+/// are compared; the rest are rejected. Ties on expected cost go to the
+/// smaller upfront cost, then to the earlier offer.
+///
+/// On failure returns the rationale for rejecting every offer.
+pub fn choose(
+    offers: &[X402PaymentRequirement],
+    workload: &Workload,
+    policy: &OfferPolicy,
+    headroom: Amount,
+    max_fee: impl Fn(&X402PaymentRequirement) -> Amount,
+) -> Result<Negotiation, String> {
+    let mut rejected = Vec::new();
+    let mut best: Option<(&X402PaymentRequirement, Amount, Amount, Amount)> = None;
+
+    for offer in offers {
+        if let Some(reason) = policy.rejects(offer) {
+            rejected.push(format!("{}: {}", describe(offer), reason));
+            continue;
+        }
+        if let Some(token) = offer.token.as_deref().filter(|token| !token.eq_ignore_ascii_case(SYMBOL)) {
+            rejected.push(format!("{}: priced in {}, not {}", describe(offer), token, SYMBOL));
+            continue;
+        }
+
+        let upfront = upfront_cost(offer);
+        let fee = max_fee(offer);
+        if upfront.saturating_add(fee) > headroom {
+            rejected.push(format!(
+                "{}: needs {} up front and up to {} gas, {} headroom",
+                describe(offer),
+                upfront,
+                fee,
+                headroom
+            ));
+            continue;
+        }
+
+        let cost = expected_cost(offer, workload);
+        let better = match best {
+            None => true,
+            Some((_, best_cost, best_upfront, _)) => (cost, upfront) < (best_cost, best_upfront),
+        };
+        if better {
+            best = Some((offer, cost, upfront, fee));
+        }
+    }
+
+    let rejected_note = if rejected.is_empty() {
+        String::new()
+    } else {
+        format!("; rejected {}", rejected.join(", "))
+    };
+
+    match best {
+        Some((offer, expected_cost, upfront_cost, max_fee)) => Ok(Negotiation {
+            chosen: offer.clone(),
+            expected_cost,
+            upfront_cost,
+            max_fee,
+            offers_considered: offers.len(),
+            rationale: format!(
+                "{} is cheapest for {} request(s) over {}s: expected {}{}",
+                describe(offer),
+                workload.requests,
+                workload.duration_secs,
+                expected_cost.with_symbol(offer.token.as_deref().unwrap_or("TCRO")),
+                rejected_note
+            ),
+        }),
+        None => Err(format!("no acceptable offer among {}{}", offers.len(), rejected_note)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::address::Address;
+    use crate::x402::HeaderDialect;
+
+    fn tcro(value: &str) -> Amount {
+        value.parse().unwrap()
+    }
+
+    fn per_request(price: &str) -> X402PaymentRequirement {
+        X402PaymentRequirement {
+            recipient: "0x70997970C51812dc3A010C7d01b50e0d17dc79C8".parse::<Address>().unwrap(),
+            amount: Some(tcro(price)),
+            mode: PaymentMode::PerRequest,
+            rate_per_second: None,
+            min_deposit: None,
+            description: None,
+            network: None,
+            token: Some(SYMBOL.to_string()),
+            contract: None,
+            scheme: None,
+            dialect: HeaderDialect::PayStream,
+        }
+    }
+
+    fn streaming(rate: &str, min_deposit: &str) -> X402PaymentRequirement {
+        X402PaymentRequirement {
+            amount: None,
+            mode: PaymentMode::Streaming,
+            rate_per_second: Some(tcro(rate)),
+            min_deposit: Some(tcro(min_deposit)),
+            ..per_request("0")
+        }
+    }
+
+    fn with_token(offer: X402PaymentRequirement, token: Option<&str>) -> X402PaymentRequirement {
+        X402PaymentRequirement {
+            token: token.map(String::from),
+            ..offer
+        }
+    }
+
+    fn workload(requests: u64, duration_secs: u64) -> Workload {
+        Workload { requests, duration_secs }
+    }
+
+    fn no_gas(_: &X402PaymentRequirement) -> Amount {
+        Amount::ZERO
+    }
+
+    #[test]
+    fn picks_the_cheapest_offer_for_the_workload() {
+        let offers = [per_request("0.01"), streaming("0.0001", "0.1")];
+
+        // 5 calls cost 0.05; the stream's 0.1 deposit is larger
+        let few = choose(&offers, &workload(5, 60), &OfferPolicy::default(), tcro("1"), no_gas).unwrap();
+        assert_eq!(few.chosen.mode, PaymentMode::PerRequest);
+        assert_eq!((few.expected_cost, few.upfront_cost), (tcro("0.05"), tcro("0.01")));
+
+        // 100 calls cost 1; streaming for 1,200s costs 0.12, of which only
+        // the 0.1 deposit is paid up front
+        let many = choose(&offers, &workload(100, 1200), &OfferPolicy::default(), tcro("1"), no_gas).unwrap();
+        assert_eq!(many.chosen.mode, PaymentMode::Streaming);
+        assert_eq!((many.expected_cost, many.upfront_cost), (tcro("0.12"), tcro("0.1")));
+        assert_eq!(many.offers_considered, 2);
+    }
+
+    #[test]
+    fn applies_the_policy() {
+        let on_testnet = X402PaymentRequirement {
+            network: Some("cronos-testnet".to_string()),
+            ..per_request("0.01")
+        };
+        let on_mainnet = X402PaymentRequirement {
+            network: Some("cronos".to_string()),
+            ..per_request("0.001")
+        };
+        let policy = OfferPolicy {
+            allowed_networks: vec!["Cronos-Testnet".to_string()],
+            allowed_tokens: Vec::new(),
+        };
+
+        let chosen = choose(&[on_mainnet, on_testnet.clone()], &workload(1, 0), &policy, tcro("1"), no_gas).unwrap();
+        assert_eq!(chosen.chosen, on_testnet);
+        assert!(chosen.rationale.contains("network cronos not allowed"), "{}", chosen.rationale);
+
+        let tokens = OfferPolicy {
+            allowed_networks: Vec::new(),
+            allowed_tokens: vec!["USDC".to_string()],
+        };
+        let refused = choose(&[per_request("0.01")], &workload(1, 0), &tokens, tcro("1"), no_gas).unwrap_err();
+        assert!(refused.contains("token TCRO not allowed"), "{}", refused);
+
+        // Offers naming no network or token pass any policy
+        let unnamed = with_token(per_request("0.01"), None);
+        assert!(choose(&[unnamed], &workload(1, 0), &policy, tcro("1"), no_gas).is_ok());
+    }
+
+    #[test]
+    fn compares_only_native_token_offers() {
+        // Cheaper in its own units, but not payable from a TCRO budget
+        let usdc = with_token(per_request("0.000001"), Some("USDC"));
+        let lowercase = with_token(per_request("0.01"), Some("tcro"));
+
+        let chosen = choose(&[usdc.clone(), lowercase.clone()], &workload(1, 0), &OfferPolicy::default(), tcro("1"), no_gas)
+            .unwrap();
+        assert_eq!(chosen.chosen, lowercase);
+        assert!(chosen.rationale.contains("priced in USDC, not TCRO"), "{}", chosen.rationale);
+
+        assert!(choose(&[usdc], &workload(1, 0), &OfferPolicy::default(), tcro("1"), no_gas).is_err());
+    }
+
+    #[test]
+    fn leaves_out_offers_the_headroom_cannot_cover() {
+        let offers = [streaming("0.0001", "0.5"), per_request("0.2")];
+
+        // The stream is cheaper over 100 calls but needs 0.5 up front
+        let chosen = choose(&offers, &workload(100, 60), &OfferPolicy::default(), tcro("0.3"), no_gas).unwrap();
+        assert_eq!(chosen.chosen.mode, PaymentMode::PerRequest);
+        assert!(
+            chosen.rationale.contains("needs 0.5 up front and up to 0.0 gas, 0.3 headroom"),
+            "{}",
+            chosen.rationale
+        );
+
+        let refused = choose(&offers, &workload(100, 60), &OfferPolicy::default(), tcro("0.1"), no_gas).unwrap_err();
+        assert!(refused.starts_with("no acceptable offer among 2"), "{}", refused);
+    }
+
+    #[test]
+    fn counts_gas_against_the_headroom() {
+        let offers = [per_request("0.2")];
+        let gas = |_: &X402PaymentRequirement| tcro("0.001");
+
+        assert!(choose(&offers, &workload(1, 0), &OfferPolicy::default(), tcro("0.2"), no_gas).is_ok());
+        let refused = choose(&offers, &workload(1, 0), &OfferPolicy::default(), tcro("0.2"), gas).unwrap_err();
+        assert!(refused.contains("needs 0.2 up front and up to 0.001 gas, 0.2 headroom"), "{}", refused);
+
+        let chosen = choose(&offers, &workload(1, 0), &OfferPolicy::default(), tcro("0.201"), gas).unwrap();
+        assert_eq!((chosen.upfront_cost, chosen.max_fee), (tcro("0.2"), tcro("0.001")));
+    }
+
+    #[test]
+    fn breaks_ties_on_upfront_cost_then_order() {
+        // Both cost 0.1 for 10 calls; the per-request offer needs less up front
+        let offers = [streaming("0.0001", "0.1"), per_request("0.01")];
+        let chosen = choose(&offers, &workload(10, 60), &OfferPolicy::default(), tcro("1"), no_gas).unwrap();
+        assert_eq!(chosen.chosen.mode, PaymentMode::PerRequest);
+
+        let first = X402PaymentRequirement {
+            description: Some("first".to_string()),
+            ..per_request("0.01")
+        };
+        let offers = [first.clone(), per_request("0.01")];
+        let chosen = choose(&offers, &workload(1, 0), &OfferPolicy::default(), tcro("1"), no_gas).unwrap();
+        assert_eq!(chosen.chosen, first);
+    }
+}
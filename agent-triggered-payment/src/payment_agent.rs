@@ -1,28 +1,177 @@
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, Weak};
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use thiserror::Error;
 use tracing::{info, warn};
 use uuid::Uuid;
 
+use crate::address::Address;
+use crate::amount::Amount;
+use crate::backend::{MemoryBackend, PaymentBackend};
+use crate::budget::{Budget, BudgetWindow, Reservation};
+use crate::chain::{self, CancelledStream, ChainConfig, ChainError, PayStreamChain, Transfer, CREATE_STREAM_GAS, TRANSFER_GAS};
 use crate::gemini::GeminiClient;
-use crate::x402::{X402PaymentRequirement, PaymentProof, PaymentMode, headers};
+use crate::negotiation::{self, Negotiation, OfferPolicy, Workload};
+use crate::sizing::{SizingError, StreamSize};
+use crate::streams::{self, StreamInfo, StreamRegistry};
+use crate::wallet::{self, AgentWallet, HdWallet, WalletError};
+use crate::x402::{HeaderDialect, X402Error, X402PaymentRequirement, X402Protocol, PaymentProof, PaymentMode, DEFAULT_AMOUNT, DEFAULT_RATE};
+use crate::x402_spec::{self, PaymentPayload, PaymentRequiredResponse, SettlementResponse};
+
+/// Errors from the x402 fetch → pay → retry flow
+#[derive(Error, Debug)]
+pub enum PaymentError {
+    /// The initial request never got a response
+    #[error("Request failed: {0}")]
+    Network(#[from] reqwest::Error),
+    /// A 402 arrived without a usable payment requirement
+    #[error("Invalid 402 challenge: {0}")]
+    InvalidChallenge(#[from] X402Error),
+    /// Paying would take spending past the agent's budget
+    #[error("Budget exceeded: payment of {spend} TCRO exceeds remaining {remaining} TCRO")]
+    BudgetExceeded {
+        requirement: Box<X402PaymentRequirement>,
+        spend: Amount,
+        remaining: Amount,
+    },
+    /// None of the offered payment options is acceptable
+    #[error("No acceptable payment offer: {rationale}")]
+    NoAcceptableOffer {
+        offers: Vec<X402PaymentRequirement>,
+        rationale: String,
+    },
+    /// The challenge asks for a stream the contract would refuse or that
+    /// would pay less than the advertised rate
+    #[error("Cannot open stream: {source}")]
+    InvalidStream {
+        requirement: Box<X402PaymentRequirement>,
+        #[source]
+        source: SizingError,
+    },
+    /// The payment itself could not be made
+    #[error("Payment failed: {source}")]
+    PaymentFailed {
+        requirement: Box<X402PaymentRequirement>,
+        #[source]
+        source: ChainError,
+    },
+    /// Payment was made but the retried request never got a response
+    #[error("Retry after payment failed: {source}")]
+    RetryFailed {
+        requirement: Box<X402PaymentRequirement>,
+        proof: Box<PaymentProof>,
+        #[source]
+        source: reqwest::Error,
+    },
+    /// Payment was made but the service still refused the request
+    #[error("Payment rejected by service: HTTP {status}")]
+    Rejected {
+        requirement: Box<X402PaymentRequirement>,
+        proof: Box<PaymentProof>,
+        status: u16,
+        body: String,
+    },
+}
+
+impl PaymentError {
+    /// The requirement being paid for, if the challenge was parsed
+    pub fn requirement(&self) -> Option<&X402PaymentRequirement> {
+        match self {
+            Self::BudgetExceeded { requirement, .. }
+            | Self::InvalidStream { requirement, .. }
+            | Self::PaymentFailed { requirement, .. }
+            | Self::RetryFailed { requirement, .. }
+            | Self::Rejected { requirement, .. } => Some(requirement),
+            Self::Network(_) | Self::InvalidChallenge(_) | Self::NoAcceptableOffer { .. } => None,
+        }
+    }
+
+    /// Proof of a payment that was already made, e.g. to request a refund
+    pub fn proof(&self) -> Option<&PaymentProof> {
+        match self {
+            Self::RetryFailed { proof, .. } | Self::Rejected { proof, .. } => Some(proof),
+            _ => None,
+        }
+    }
+}
 
 /// Agent configuration
 #[derive(Debug, Clone)]
 pub struct AgentConfig {
     pub name: String,
-    pub wallet_address: String,
-    pub daily_budget: f64,
+    /// Paying address. Replaced by the address derived from the signing key
+    /// once a wallet or chain is attached.
+    pub wallet_address: Address,
+    pub daily_budget: Amount,
+    /// When `daily_budget` resets. Both are read when the agent is created.
+    pub budget_window: BudgetWindow,
+    /// BIP-44 account to derive from a fleet mnemonic, see
+    /// [`PaymentAgent::with_hd_wallet`]. Defaults to account 0.
+    pub hd_index: Option<u32>,
+    /// Pay each service host from its own HD child address, so services do
+    /// not see one payer address across hosts. Each child is funded from the
+    /// agent's key, so the children remain linkable on chain.
+    pub fresh_address_per_service: bool,
+    /// JSON file keeping the host → child index map across restarts, so a
+    /// host keeps its child and no child is reassigned to another host.
+    /// Without it the map lasts as long as the agent.
+    pub service_index_file: Option<PathBuf>,
+    /// Cancel streams that have carried no request for this long and
+    /// reclaim the unstreamed deposit. `None` keeps streams to their stop time.
+    pub idle_stream_timeout: Option<Duration>,
+    /// Open a replacement stream this long before a stream in use runs dry,
+    /// so requests never wait on a fresh 402. `None` disables top-ups.
+    pub stream_top_up_lead: Option<Duration>,
+    /// x402 network name of the chain payments settle on. Open x402 spec
+    /// offers on other networks are skipped.
+    pub network: String,
+    /// Simulate settlement on an in-memory ledger. Requests, 402 parsing,
+    /// budgets, offer policies and stream reuse work as usual, and results
+    /// are marked [`FetchResult::simulated`]. Simulated proofs are never
+    /// sent: a paid request ends at the 402, with the proof in the result.
+    pub dry_run: bool,
+}
+
+impl Default for AgentConfig {
+    /// An agent with no budget and every optional behaviour off. Set at
+    /// least `name`, `wallet_address` and `daily_budget`.
+    fn default() -> Self {
+        Self {
+            name: "agent".to_string(),
+            wallet_address: Address::ZERO,
+            daily_budget: Amount::ZERO,
+            budget_window: BudgetWindow::default(),
+            hd_index: None,
+            fresh_address_per_service: false,
+            service_index_file: None,
+            idle_stream_timeout: None,
+            stream_top_up_lead: None,
+            network: x402_spec::CRONOS_TESTNET.to_string(),
+            dry_run: false,
+        }
+    }
 }
 
 /// Stats tracking for the agent
 #[derive(Debug, Default)]
 pub struct AgentStats {
     pub requests_made: AtomicU64,
+    /// Payments settled, including those the service then rejected
     pub payments_made: AtomicU64,
-    pub total_spent: std::sync::atomic::AtomicU64, // Store as micro-units
-    pub active_streams: AtomicU64,
+    /// Value and gas paid, net of stream refunds
+    pub total_spent: Mutex<Amount>,
+    pub total_refunded: Mutex<Amount>,
+    /// Streams cancelled by the idle reaper
+    pub idle_streams_cancelled: AtomicU64,
+    /// Deposit refunded by idle-stream cancellations
+    pub idle_reclaimed: Mutex<Amount>,
+    /// Replacement streams opened ahead of depletion
+    pub streams_topped_up: AtomicU64,
 }
 
 /// Result of a fetch operation
@@ -32,62 +181,410 @@ pub struct FetchResult {
     pub body: String,
     pub payment_made: bool,
     pub stream_id: Option<u64>,
-    pub amount_spent: Option<String>,
+    pub amount_spent: Option<Amount>,
+    /// Decoded `X-PAYMENT-RESPONSE`, when the service sent one
+    pub settlement: Option<SettlementResponse>,
+    /// Offer chosen for the 402 and why
+    pub negotiation: Option<Negotiation>,
+    /// Proof the request was paid with
+    #[serde(default)]
+    pub proof: Option<PaymentProof>,
+    /// Settlement was simulated, by a dry run or an in-memory backend, so
+    /// no funds moved
+    #[serde(default)]
+    pub simulated: bool,
+}
+
+/// Per-request options for [`PaymentAgent::fetch_with`]
+#[derive(Debug, Clone, Default)]
+pub struct FetchOptions {
+    pub protocol: X402Protocol,
+    /// Expected use, for pricing offers against each other
+    pub workload: Workload,
+    pub policy: OfferPolicy,
 }
 
-/// Payment Agent - autonomous agent that can make x402 payments
-pub struct PaymentAgent {
+/// What each account starts with on the simulated ledger of [`PaymentAgent::new`]
+pub const SIMULATED_BALANCE: Amount = Amount::from_wei_u128(1_000_000_000_000_000_000_000_000); // 1,000,000 TCRO
+
+/// Payment Agent - autonomous agent that can make x402 payments, settled
+/// through a [`PaymentBackend`]
+pub struct PaymentAgent<B = MemoryBackend> {
     pub id: String,
     pub config: AgentConfig,
     pub gemini: Arc<GeminiClient>,
     http_client: Client,
     pub stats: AgentStats,
-    next_stream_id: AtomicU64,
+    budget: Budget,
+    /// Open streams, reused by later requests to the same service
+    streams: StreamRegistry,
+    wallet: Option<AgentWallet>,
+    hd: Option<HdWallet>,
+    /// HD child index paying each service host
+    service_children: Mutex<HashMap<String, u32>>,
+    backend: B,
+    /// Ledger used instead of `backend` when `config.dry_run` is set
+    dry_run_ledger: MemoryBackend,
+}
+
+/// Where one payer's transactions settle
+enum Settlement<'a, B> {
+    Backend(&'a B),
+    /// The payer's address on the dry-run ledger
+    DryRun(MemoryBackend),
+}
+
+impl<B: PaymentBackend> Deref for Settlement<'_, B> {
+    type Target = dyn PaymentBackend;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Self::Backend(backend) => *backend,
+            Self::DryRun(ledger) => ledger,
+        }
+    }
 }
 
 impl PaymentAgent {
+    /// Agent settling on a simulated in-memory ledger, where every account
+    /// starts with [`SIMULATED_BALANCE`]. See [`Self::with_backend`].
     pub fn new(config: AgentConfig, gemini: Arc<GeminiClient>) -> Self {
         let id = format!("{}-{}", config.name, &Uuid::new_v4().to_string()[..8]);
+        let budget = Budget::new(config.daily_budget, config.budget_window);
+        let backend = MemoryBackend::new(config.wallet_address).with_opening_balance(SIMULATED_BALANCE);
+        let dry_run_ledger = backend.for_sender(config.wallet_address);
         Self {
             id,
             config,
             gemini,
             http_client: Client::new(),
             stats: AgentStats::default(),
-            next_stream_id: AtomicU64::new(1000),
+            budget,
+            streams: StreamRegistry::default(),
+            wallet: None,
+            hd: None,
+            service_children: Mutex::new(HashMap::new()),
+            backend,
+            dry_run_ledger,
         }
     }
+}
+
+impl<B: PaymentBackend> PaymentAgent<B> {
+    /// Sign with the given wallet; the agent's address becomes the key's
+    pub fn with_wallet(mut self, wallet: AgentWallet) -> Self {
+        self.set_wallet_address(wallet.address());
+        self.backend = self.backend.with_wallet(&wallet);
+        self.wallet = Some(wallet);
+        self
+    }
+
+    /// Sign with account `config.hd_index` of a fleet mnemonic. Enables
+    /// per-service child addresses when `fresh_address_per_service` is set,
+    /// loading their assignment from `config.service_index_file`.
+    pub fn with_hd_wallet(self, hd: HdWallet) -> Result<Self, WalletError> {
+        let wallet = hd.agent(self.config.hd_index.unwrap_or(0))?;
+        let mut agent = self.with_wallet(wallet);
+        if let Some(ref path) = agent.config.service_index_file {
+            *agent.service_children.get_mut().unwrap() = wallet::load_service_index(path)?;
+        }
+        agent.hd = Some(hd);
+        Ok(agent)
+    }
+
+    /// Settle payments through another backend. Open streams and spend so
+    /// far carry over.
+    pub fn with_backend<C: PaymentBackend>(self, backend: C) -> PaymentAgent<C> {
+        let mut agent = PaymentAgent {
+            id: self.id,
+            config: self.config,
+            gemini: self.gemini,
+            http_client: self.http_client,
+            stats: self.stats,
+            budget: self.budget,
+            streams: self.streams,
+            wallet: self.wallet,
+            hd: self.hd,
+            service_children: self.service_children,
+            backend,
+            dry_run_ledger: self.dry_run_ledger,
+        };
+        agent.set_wallet_address(agent.backend.sender());
+        agent
+    }
+
+    /// Settle payments on chain through the given `PayStreamStream` client
+    pub fn with_chain(self, chain: PayStreamChain) -> PaymentAgent<PayStreamChain> {
+        self.with_backend(chain)
+    }
+
+    /// Connect to `PayStreamStream` with the attached wallet and settle
+    /// payments on chain
+    pub async fn connect_chain(self, config: &ChainConfig) -> Result<PaymentAgent<PayStreamChain>, ChainError> {
+        let wallet = self
+            .wallet
+            .as_ref()
+            .ok_or_else(|| ChainError::Config("no wallet attached to sign with".to_string()))?;
+        let chain = PayStreamChain::connect(config, wallet).await?;
+        Ok(self.with_chain(chain))
+    }
+
+    /// The attached signing wallet, if any
+    pub fn wallet(&self) -> Option<&AgentWallet> {
+        self.wallet.as_ref()
+    }
+
+    /// Where payments settle, unless this is a dry run
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    /// Whether payments are simulated rather than settled for real
+    pub fn is_simulated(&self) -> bool {
+        self.config.dry_run || self.backend.is_simulated()
+    }
+
+    /// Settle `payer`'s transactions, on the dry-run ledger when
+    /// `config.dry_run` is set
+    fn settle<'a>(&'a self, payer: &'a B) -> Settlement<'a, B> {
+        if self.config.dry_run {
+            Settlement::DryRun(self.dry_run_ledger.for_sender(payer.sender()))
+        } else {
+            Settlement::Backend(payer)
+        }
+    }
+
+    /// Current Unix time on the ledger payments settle on. Stream expiry,
+    /// idleness and depletion are all judged by this clock.
+    fn now(&self) -> u64 {
+        if self.config.dry_run {
+            self.dry_run_ledger.now()
+        } else {
+            self.backend.now()
+        }
+    }
+
+    /// Child address paying each service host so far
+    pub fn service_addresses(&self) -> Vec<(String, Address)> {
+        let Some(ref hd) = self.hd else {
+            return Vec::new();
+        };
+        let account = self.config.hd_index.unwrap_or(0);
+        let children = self.service_children.lock().unwrap();
+        let mut addresses: Vec<_> = children
+            .iter()
+            .filter_map(|(host, child)| Some((host.clone(), hd.service_child(account, *child).ok()?.address())))
+            .collect();
+        addresses.sort();
+        addresses
+    }
+
+    /// Sweep what is left on the per-service child addresses used so far
+    /// back to the agent's own address
+    pub async fn sweep_service_wallets(&self) -> Result<Vec<Transfer>, ChainError> {
+        let Some(ref hd) = self.hd else {
+            return Ok(Vec::new());
+        };
+        let account = self.config.hd_index.unwrap_or(0);
+        let mut children: Vec<u32> = self.service_children.lock().unwrap().values().copied().collect();
+        children.sort_unstable();
+
+        let mut swept = Vec::new();
+        for child in children {
+            let wallet = hd
+                .service_child(account, child)
+                .map_err(|e| ChainError::Config(e.to_string()))?;
+            let child = self.backend.with_wallet(&wallet);
+            if let Some(transfer) = self.settle(&child).sweep(self.backend.sender()).await? {
+                info!("🧹 Swept {} TCRO from {}", transfer.amount, wallet.address().short());
+                swept.push(transfer);
+            }
+        }
+        Ok(swept)
+    }
+
+    /// A funded child-address backend to pay `url` from, when fresh
+    /// addresses per service are enabled. The gas paid to fund it is
+    /// charged out of `reservation` at once, as it is spent even if the
+    /// payment after it fails.
+    async fn service_payer(
+        &self,
+        url: &str,
+        requirement: &X402PaymentRequirement,
+        value: Amount,
+        gas_limit: u64,
+        reservation: &mut Reservation<'_>,
+    ) -> Result<Option<B>, PaymentError> {
+        let Some(ref hd) = self.hd else {
+            return Ok(None);
+        };
+        if !self.config.fresh_address_per_service {
+            return Ok(None);
+        }
+
+        let failed = |source| PaymentError::PaymentFailed {
+            requirement: Box::new(requirement.clone()),
+            source,
+        };
+        let child = self
+            .service_child_index(streams::host_of(url))
+            .map_err(|e| failed(ChainError::Config(e.to_string())))?;
+        let wallet = hd
+            .service_child(self.config.hd_index.unwrap_or(0), child)
+            .map_err(|e| failed(ChainError::Config(e.to_string())))?;
+        info!("   ├─ Paying from service address {}", wallet.address().short());
+        let funding = self
+            .settle(&self.backend)
+            .fund(wallet.address(), value, gas_limit)
+            .await
+            .map_err(failed)?;
+
+        if let Some(transfer) = funding {
+            reservation.commit_part(transfer.gas_cost);
+            self.record_spend(transfer.gas_cost);
+        }
+        Ok(Some(self.backend.with_wallet(&wallet)))
+    }
+
+    /// HD child index paying `host`, assigning the next unused one to a new
+    /// host. New assignments are saved before any child is funded.
+    fn service_child_index(&self, host: String) -> Result<u32, WalletError> {
+        let mut children = self.service_children.lock().unwrap();
+        if let Some(child) = children.get(&host) {
+            return Ok(*child);
+        }
+        let next = children.values().max().map_or(1, |last| last + 1);
+        children.insert(host.clone(), next);
+        if let Some(ref path) = self.config.service_index_file {
+            if let Err(e) = wallet::save_service_index(path, &children) {
+                children.remove(&host);
+                return Err(e);
+            }
+        }
+        Ok(next)
+    }
+
+    /// Most the gas for a payment with `gas_limit` can cost, including the
+    /// transfer funding a per-service address first
+    async fn max_fee(&self, requirement: &X402PaymentRequirement, gas_limit: u64) -> Result<Amount, PaymentError> {
+        Ok(self.max_fee_at(self.gas_price(requirement).await?, gas_limit))
+    }
+
+    /// [`Self::max_fee`] at a known gas price
+    fn max_fee_at(&self, price: Amount, gas_limit: u64) -> Amount {
+        let fee = chain::max_fee(price, gas_limit);
+        if self.hd.is_some() && self.config.fresh_address_per_service {
+            fee.saturating_add(chain::max_fee(price, TRANSFER_GAS))
+        } else {
+            fee
+        }
+    }
+
+    /// Current gas price where payments settle
+    async fn gas_price(&self, requirement: &X402PaymentRequirement) -> Result<Amount, PaymentError> {
+        self.settle(&self.backend)
+            .gas_price()
+            .await
+            .map_err(|source| PaymentError::PaymentFailed {
+                requirement: Box::new(requirement.clone()),
+                source,
+            })
+    }
+
+    fn set_wallet_address(&mut self, address: Address) {
+        if self.config.wallet_address != address && !self.config.wallet_address.is_zero() {
+            warn!(
+                "⚠️ Configured wallet {} does not match signing key {}; using the key's address",
+                self.config.wallet_address.short(),
+                address.short()
+            );
+        }
+        self.config.wallet_address = address;
+    }
 
     /// Fetch a URL, automatically handling x402 payment requirements
-    pub async fn fetch(&self, url: &str) -> Result<FetchResult, String> {
+    pub async fn fetch(&self, url: &str) -> Result<FetchResult, PaymentError> {
+        self.fetch_with(url, &FetchOptions::default()).await
+    }
+
+    /// Fetch a URL, reading any 402 challenge in the requested protocol and
+    /// paying for the offer that best fits the declared workload
+    pub async fn fetch_with(&self, url: &str, options: &FetchOptions) -> Result<FetchResult, PaymentError> {
         info!("📡 Fetching: {}", url);
         self.stats.requests_made.fetch_add(1, Ordering::Relaxed);
 
-        // Make initial request
-        let response = self.http_client.get(url)
-            .send()
-            .await
-            .map_err(|e| format!("Request failed: {}", e))?;
+        // Make initial request, on a stream opened for this route if there is
+        // one. Streams on the dry-run ledger are never shown to a service.
+        let now = self.now();
+        let cached = if self.config.dry_run {
+            None
+        } else {
+            self.streams.for_route(&streams::route_of(url), now)
+        };
+        let mut request = self.http_client.get(url);
+        if let Some(ref stream) = cached {
+            info!("♻️  Attaching open stream #{}", stream.stream_id);
+            self.streams.touch(stream.stream_id, now);
+            for (name, value) in stream.proof().headers() {
+                request = request.header(name, value);
+            }
+        }
+        let response = request.send().await?;
 
         let status = response.status().as_u16();
 
         // Check for 402 Payment Required
         if status == 402 {
             info!("⚠️  HTTP 402 Payment Required");
-            
-            // Parse payment requirements from headers
-            if let Some(requirement) = X402PaymentRequirement::from_response(&response) {
-                info!("   {}", requirement.display());
-                
-                // Trigger payment
-                let proof = self.trigger_payment(&requirement).await?;
-                
-                // Retry request with payment proof
-                return self.retry_with_payment(url, &proof).await;
-            } else {
-                warn!("   ⚠️ Could not parse payment requirements from 402 response");
-                return Err("402 received but no valid x402 headers found".to_string());
+            let headers = response.headers().clone();
+            let body = response.text().await.unwrap_or_default();
+
+            let parsed = match options.protocol {
+                // Parse payment requirements from headers, falling back to the JSON body
+                X402Protocol::PayStream => X402PaymentRequirement::offers(&headers, &body),
+                // Every payable entry of the `accepts` array
+                X402Protocol::Spec => PaymentRequiredResponse::from_json_body(&body)
+                    .and_then(|challenge| challenge.requirements(&self.config.network)),
+            };
+
+            let offers = match parsed {
+                Ok(offers) => offers,
+                Err(e) => {
+                    warn!("   ⚠️ Could not parse payment requirements from 402 response: {}", e);
+                    return Err(e.into());
+                }
+            };
+
+            // The attached stream was refused only if the service still wants
+            // a stream to the same recipient; otherwise it may pay other routes
+            if let Some(ref stream) = cached {
+                let refused = offers
+                    .iter()
+                    .any(|offer| offer.mode == PaymentMode::Streaming && offer.recipient == stream.recipient);
+                if refused {
+                    warn!("   ⚠️ Stream #{} was refused; forgetting it", stream.stream_id);
+                    self.streams.remove_id(stream.stream_id);
+                }
             }
+
+            let negotiation = self.choose_offer(offers, options).await?;
+            let requirement = negotiation.chosen.clone();
+            info!("   {}", requirement.display());
+            if negotiation.offers_considered > 1 {
+                info!("   🤝 {}", negotiation.rationale);
+            }
+
+            let proof = self.pay_or_reuse(url, &requirement).await?;
+
+            // A simulated proof would only be refused, so a dry run stops here
+            let mut result = if self.config.dry_run {
+                info!("🔸 Dry run: not retrying with the simulated proof");
+                self.paid_result(status, body, &proof, None)
+            } else {
+                self.retry_with_payment(url, &requirement, proof).await?
+            };
+            result.negotiation = Some(negotiation);
+            return Ok(result);
         }
 
         // Success case
@@ -97,13 +594,17 @@ impl PaymentAgent {
             status,
             body,
             payment_made: false,
-            stream_id: None,
+            stream_id: cached.map(|stream| stream.stream_id),
             amount_spent: None,
+            settlement: None,
+            negotiation: None,
+            proof: None,
+            simulated: self.is_simulated(),
         })
     }
 
     /// Simulate fetching with a mock 402 response (for demo purposes)
-    pub async fn fetch_with_mock_402(&self, url: &str, mock_requirement: X402PaymentRequirement) -> Result<FetchResult, String> {
+    pub async fn fetch_with_mock_402(&self, url: &str, mock_requirement: X402PaymentRequirement) -> Result<FetchResult, PaymentError> {
         info!("📡 Fetching: {}", url);
         self.stats.requests_made.fetch_add(1, Ordering::Relaxed);
 
@@ -111,113 +612,225 @@ impl PaymentAgent {
         info!("⚠️  HTTP 402 Payment Required");
         info!("   {}", mock_requirement.display());
 
-        // Trigger payment
-        let proof = self.trigger_payment(&mock_requirement).await?;
+        let proof = self.pay_or_reuse(url, &mock_requirement).await?;
 
         // Simulate successful retry
         info!("🔄 Retrying request with payment proof...");
-        
-        self.stats.payments_made.fetch_add(1, Ordering::Relaxed);
+        Ok(self.paid_result(200, self.generate_mock_response(url), &proof, None))
+    }
 
-        Ok(FetchResult {
-            status: 200,
-            body: self.generate_mock_response(url),
-            payment_made: true,
-            stream_id: proof.stream_id,
-            amount_spent: Some(proof.amount_paid),
-        })
+    /// Choose among offered payment options by expected cost for the
+    /// workload, the allowed networks and tokens, and budget headroom for
+    /// the payment and its gas
+    pub async fn choose_offer(
+        &self,
+        offers: Vec<X402PaymentRequirement>,
+        options: &FetchOptions,
+    ) -> Result<Negotiation, PaymentError> {
+        let price = match offers.first() {
+            Some(offer) => self.gas_price(offer).await?,
+            None => Amount::ZERO,
+        };
+        let max_fee = |offer: &X402PaymentRequirement| {
+            let gas_limit = match offer.mode {
+                PaymentMode::Streaming => CREATE_STREAM_GAS,
+                PaymentMode::PerRequest => TRANSFER_GAS,
+            };
+            self.max_fee_at(price, gas_limit)
+        };
+        let headroom = self.budget.remaining();
+        negotiation::choose(&offers, &options.workload, &options.policy, headroom, max_fee)
+            .map_err(|rationale| PaymentError::NoAcceptableOffer { offers, rationale })
+    }
+
+    /// Reuse an open stream to the requirement's recipient, else pay
+    async fn pay_or_reuse(&self, url: &str, requirement: &X402PaymentRequirement) -> Result<PaymentProof, PaymentError> {
+        match self.reusable_stream(&streams::host_of(url), requirement) {
+            Some(stream) => Ok(stream.proof()),
+            None => self.trigger_payment(url, requirement).await,
+        }
     }
 
     /// Trigger a payment based on the requirement
-    async fn trigger_payment(&self, requirement: &X402PaymentRequirement) -> Result<PaymentProof, String> {
+    async fn trigger_payment(&self, url: &str, requirement: &X402PaymentRequirement) -> Result<PaymentProof, PaymentError> {
+        if self.config.dry_run {
+            info!("🔸 Dry run: settlement is simulated");
+        }
         match requirement.mode {
             PaymentMode::Streaming => {
-                let deposit = requirement.min_deposit.clone()
-                    .unwrap_or_else(|| "1.00".to_string());
-                let rate = requirement.rate_per_second.clone()
-                    .unwrap_or_else(|| "0.0001".to_string());
+                // Checked before anything is reserved or funded
+                let size = StreamSize::for_requirement(requirement).map_err(|source| PaymentError::InvalidStream {
+                    requirement: Box::new(requirement.clone()),
+                    source,
+                })?;
+                let deposit = size.deposit;
+                let rate = requirement.rate_per_second.unwrap_or(DEFAULT_RATE);
+                let max_fee = self.max_fee(requirement, CREATE_STREAM_GAS).await?;
+                let mut reservation = self.reserve(requirement, deposit.saturating_add(max_fee))?;
                 
                 info!("💳 Creating payment stream...");
                 info!("   ├─ Deposit: {} TCRO", deposit);
-                info!("   ├─ Rate: {}/sec", rate);
+                info!("   ├─ Rate: {}/sec (on chain: {}/sec over {}s)", rate, size.flow_rate, size.duration_secs);
                 
-                // Generate stream ID (in real impl, this would call FlowPay contract)
-                let stream_id = self.next_stream_id.fetch_add(1, Ordering::Relaxed);
+                let service_payer = self.service_payer(url, requirement, deposit, CREATE_STREAM_GAS, &mut reservation).await?;
+                let duration = size.duration_secs;
+                let payer = service_payer.as_ref().unwrap_or(&self.backend);
+                let metadata = serde_json::json!({
+                    "agentId": self.id,
+                    "timestamp": chrono::Utc::now().timestamp_millis(),
+                    "serviceUrl": url,
+                    "purpose": requirement.description.clone().unwrap_or_else(|| "x402 payment".to_string()),
+                });
+
+                let created = self
+                    .settle(payer)
+                    .open_stream(requirement.recipient, duration, deposit, &metadata.to_string())
+                    .await
+                    .map_err(|source| PaymentError::PaymentFailed {
+                        requirement: Box::new(requirement.clone()),
+                        source,
+                    })?;
+                let proof = PaymentProof::streaming(created.stream_id, deposit, requirement.dialect)
+                    .with_receipt(created.block_number, created.gas_cost);
+                let stream_id = created.stream_id;
                 info!("   └─ Stream ID: #{}", stream_id);
 
-                self.stats.active_streams.fetch_add(1, Ordering::Relaxed);
-                
-                // Track spending (convert to micro-units for atomic tracking)
-                if let Ok(deposit_val) = deposit.parse::<f64>() {
-                    let micro = (deposit_val * 1_000_000.0) as u64;
-                    self.stats.total_spent.fetch_add(micro, Ordering::Relaxed);
-                }
+                let spent = deposit.saturating_add(created.gas_cost);
+                let spend = reservation.commit_with(spent);
+                self.record_payment(spent);
+                self.streams.insert(StreamInfo {
+                    stream_id,
+                    sender: payer.sender(),
+                    recipient: requirement.recipient,
+                    host: streams::host_of(url),
+                    service_url: url.to_string(),
+                    deposit,
+                    rate_per_second: rate,
+                    start_time: created.start_time,
+                    expires_at: created.stop_time,
+                    dialect: requirement.dialect,
+                    spend: Some(spend),
+                    last_used: created.start_time,
+                });
 
-                Ok(PaymentProof::streaming(stream_id, &deposit))
+                Ok(proof)
             }
             PaymentMode::PerRequest => {
-                let amount = requirement.amount.clone()
-                    .unwrap_or_else(|| "0.001".to_string());
+                let amount = requirement.amount.unwrap_or(DEFAULT_AMOUNT);
+                let max_fee = self.max_fee(requirement, TRANSFER_GAS).await?;
+                let mut reservation = self.reserve(requirement, amount.saturating_add(max_fee))?;
                 
                 info!("💳 Making per-request payment...");
                 info!("   ├─ Amount: {} TCRO", amount);
                 
-                // Simulate tx hash
-                let uuid_str = Uuid::new_v4().to_string().replace("-", "");
-                let tx_hash = format!("0x{}", &uuid_str[..40.min(uuid_str.len())]);
+                let service_payer = self.service_payer(url, requirement, amount, TRANSFER_GAS, &mut reservation).await?;
+                let payer = service_payer.as_ref().unwrap_or(&self.backend);
+                let transfer = self
+                    .settle(payer)
+                    .pay(requirement.recipient, amount)
+                    .await
+                    .map_err(|source| PaymentError::PaymentFailed {
+                        requirement: Box::new(requirement.clone()),
+                        source,
+                    })?;
+                info!("   ├─ Gas: {} TCRO", transfer.gas_cost);
+                let proof = PaymentProof::per_request(&format!("{:?}", transfer.tx_hash), amount, requirement.dialect)
+                    .with_receipt(transfer.block_number, transfer.gas_cost);
+                let tx_hash = proof.tx_hash.as_deref().unwrap_or_default();
                 info!("   └─ TX: {}...", &tx_hash[..16]);
 
-                // Track spending
-                if let Ok(amount_val) = amount.parse::<f64>() {
-                    let micro = (amount_val * 1_000_000.0) as u64;
-                    self.stats.total_spent.fetch_add(micro, Ordering::Relaxed);
-                }
+                let spent = amount.saturating_add(transfer.gas_cost);
+                reservation.commit_with(spent);
+                self.record_payment(spent);
 
-                Ok(PaymentProof::per_request(&tx_hash, &amount))
+                Ok(proof)
             }
         }
     }
 
     /// Retry request with payment proof
-    async fn retry_with_payment(&self, url: &str, proof: &PaymentProof) -> Result<FetchResult, String> {
+    async fn retry_with_payment(
+        &self,
+        url: &str,
+        requirement: &X402PaymentRequirement,
+        proof: PaymentProof,
+    ) -> Result<FetchResult, PaymentError> {
         info!("🔄 Retrying request with payment proof...");
 
         let mut request = self.http_client.get(url);
 
-        // Add payment proof headers
-        match proof.mode {
-            PaymentMode::Streaming => {
-                if let Some(stream_id) = proof.stream_id {
-                    request = request.header(headers::FLOWPAY_STREAM, stream_id.to_string());
-                }
-            }
-            PaymentMode::PerRequest => {
-                if let Some(ref tx_hash) = proof.tx_hash {
-                    request = request.header("X-Payment-TxHash", tx_hash);
-                }
-            }
+        // Add payment proof headers in the dialect of the challenge
+        let proof_headers = match proof.dialect {
+            HeaderDialect::Spec => vec![(
+                x402_spec::headers::X_PAYMENT,
+                PaymentPayload::for_proof(requirement, &proof).encode(),
+            )],
+            HeaderDialect::FlowPay | HeaderDialect::PayStream => proof.headers(),
+        };
+        for (name, value) in proof_headers {
+            request = request.header(name, value);
         }
 
-        let response = request.send().await
-            .map_err(|e| format!("Retry request failed: {}", e))?;
+        let response = match request.send().await {
+            Ok(response) => response,
+            Err(source) => {
+                return Err(PaymentError::RetryFailed {
+                    requirement: Box::new(requirement.clone()),
+                    proof: Box::new(proof),
+                    source,
+                });
+            }
+        };
 
         let status = response.status().as_u16();
+        let settlement = match SettlementResponse::from_header_map(response.headers()) {
+            Some(Ok(settlement)) => Some(settlement),
+            Some(Err(e)) => {
+                warn!("⚠️ Ignoring undecodable settlement response: {}", e);
+                None
+            }
+            None => None,
+        };
         let body = response.text().await.unwrap_or_default();
 
-        if status == 200 {
-            info!("✅ HTTP 200 OK - Payment verified!");
-            self.stats.payments_made.fetch_add(1, Ordering::Relaxed);
-        } else {
+        if !(200..300).contains(&status) {
             warn!("⚠️ Unexpected status after payment: {}", status);
+            return Err(PaymentError::Rejected {
+                requirement: Box::new(requirement.clone()),
+                proof: Box::new(proof),
+                status,
+                body,
+            });
         }
 
-        Ok(FetchResult {
+        info!("✅ HTTP {} - Payment verified!", status);
+        Ok(self.paid_result(status, body, &proof, settlement))
+    }
+
+    /// Result of a request paid with `proof`: accepted by the service, or
+    /// still at the 402 in a dry run
+    fn paid_result(
+        &self,
+        status: u16,
+        body: String,
+        proof: &PaymentProof,
+        settlement: Option<SettlementResponse>,
+    ) -> FetchResult {
+        // Requests on a reused stream pay nothing new. New payments were
+        // counted when they settled.
+        let paid = !proof.amount_paid.is_zero();
+
+        FetchResult {
             status,
             body,
-            payment_made: true,
+            payment_made: paid,
             stream_id: proof.stream_id,
-            amount_spent: Some(proof.amount_paid.clone()),
-        })
+            amount_spent: Some(proof.amount_paid).filter(|_| paid),
+            settlement,
+            negotiation: None,
+            proof: Some(proof.clone()),
+            simulated: self.is_simulated(),
+        }
     }
 
     /// Generate mock response for demo
@@ -233,18 +846,257 @@ impl PaymentAgent {
         }
     }
 
-    /// Get total amount spent (in TCRO)
-    pub fn total_spent(&self) -> f64 {
-        self.stats.total_spent.load(Ordering::Relaxed) as f64 / 1_000_000.0
+    /// Open stream to the requirement's recipient for `host`, if streaming
+    fn reusable_stream(&self, host: &str, requirement: &X402PaymentRequirement) -> Option<StreamInfo> {
+        if requirement.mode != PaymentMode::Streaming {
+            return None;
+        }
+        let now = self.now();
+        let stream = self.streams.get(host, requirement.recipient, now)?;
+        self.streams.touch(stream.stream_id, now);
+        info!("♻️  Reusing stream #{} (expires in {}s)", stream.stream_id, stream.expires_at.saturating_sub(now));
+        Some(stream)
+    }
+
+    /// Streams currently open, including streams replaced by a top-up that
+    /// have not reached their stop time. Read from the registry on each
+    /// call, after dropping streams past their stop time.
+    pub fn active_streams(&self) -> Vec<StreamInfo> {
+        self.streams.active(self.now())
+    }
+
+    /// Cancel a stream, returning the unstreamed deposit to the sender and
+    /// crediting it back to the budget and spend totals
+    pub async fn cancel_stream(&self, stream_id: u64) -> Result<CancelledStream, ChainError> {
+        info!("🛑 Cancelling stream #{}...", stream_id);
+        let stream = self.streams.by_id(stream_id, self.now());
+
+        let child = match stream {
+            Some(ref stream) if stream.sender != self.backend.sender() => self.service_backend_for(stream.sender)?,
+            _ => None,
+        };
+        let payer = child.as_ref().unwrap_or(&self.backend);
+        let cancelled = self.settle(payer).cancel_stream(stream_id).await?;
+        info!("   ├─ Refund: {} TCRO", cancelled.refund);
+        info!("   └─ Recipient payout: {} TCRO", cancelled.payout);
+
+        if let Some(stream) = stream {
+            if let Some(spend) = stream.spend {
+                self.budget.refund(spend, cancelled.refund);
+            }
+            let mut total = self.stats.total_spent.lock().unwrap();
+            *total = total.saturating_sub(cancelled.refund);
+            drop(total);
+            let mut refunded = self.stats.total_refunded.lock().unwrap();
+            *refunded = refunded.saturating_add(cancelled.refund);
+        }
+        self.streams.remove_id(stream_id);
+
+        Ok(cancelled)
+    }
+
+    /// Cancel every open stream. Each stream's outcome is reported
+    /// separately, so one failure does not stop the rest.
+    pub async fn cancel_all_streams(&self) -> Vec<(u64, Result<CancelledStream, ChainError>)> {
+        let mut outcomes = Vec::new();
+        for stream in self.streams.active(self.now()) {
+            let outcome = self.cancel_stream(stream.stream_id).await;
+            if let Err(ref e) = outcome {
+                warn!("⚠️ Could not cancel stream #{}: {}", stream.stream_id, e);
+            }
+            outcomes.push((stream.stream_id, outcome));
+        }
+        outcomes
+    }
+
+    /// Cancel streams idle for longer than `config.idle_stream_timeout`,
+    /// adding their refunds to the reclaimed total
+    pub async fn reap_idle_streams(&self) -> Vec<CancelledStream> {
+        let Some(timeout) = self.config.idle_stream_timeout else {
+            return Vec::new();
+        };
+
+        let now = self.now();
+        let mut reaped = Vec::new();
+        for stream in self.streams.idle(timeout.as_secs(), now) {
+            info!("💤 Stream #{} idle for {}s", stream.stream_id, now.saturating_sub(stream.last_used));
+            match self.cancel_stream(stream.stream_id).await {
+                Ok(cancelled) => {
+                    self.stats.idle_streams_cancelled.fetch_add(1, Ordering::Relaxed);
+                    let mut reclaimed = self.stats.idle_reclaimed.lock().unwrap();
+                    *reclaimed = reclaimed.saturating_add(cancelled.refund);
+                    drop(reclaimed);
+                    reaped.push(cancelled);
+                }
+                Err(e) => warn!("⚠️ Could not cancel idle stream #{}: {}", stream.stream_id, e),
+            }
+        }
+        reaped
+    }
+
+    /// Run [`Self::reap_idle_streams`] every `period` in the background.
+    /// The task ends once the agent is dropped.
+    pub fn spawn_idle_reaper(self: &Arc<Self>, period: Duration) -> tokio::task::JoinHandle<()> {
+        let agent: Weak<Self> = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(period);
+            ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                ticks.tick().await;
+                let Some(agent) = agent.upgrade() else {
+                    break;
+                };
+                agent.reap_idle_streams().await;
+            }
+        })
+    }
+
+    /// Open a replacement for each stream in use that will run dry within
+    /// `config.stream_top_up_lead`. Later requests go out on the replacement.
+    /// The old stream stays open and tracked until its stop time, so requests
+    /// already sent on it still settle, and it is counted in
+    /// [`Self::active_streams`] and cancelled by [`Self::cancel_all_streams`].
+    /// Replacements are paid from the budget like any other stream and are
+    /// skipped when it is exhausted.
+    pub async fn top_up_streams(&self) -> Vec<StreamInfo> {
+        let Some(lead) = self.config.stream_top_up_lead else {
+            return Vec::new();
+        };
+
+        let now = self.now();
+        let mut replacements = Vec::new();
+        for stream in self.streams.active(now) {
+            if stream.depletes_at(now) > now.saturating_add(lead.as_secs()) {
+                continue;
+            }
+            // Streams nobody reused, or that the reaper is about to cancel, are left to lapse
+            let idle = self
+                .config
+                .idle_stream_timeout
+                .is_some_and(|timeout| stream.is_idle_at(now, timeout.as_secs()));
+            if stream.last_used <= stream.start_time || idle {
+                continue;
+            }
+            // Already replaced by a stream with more time left
+            let newest = self.streams.get(&stream.host, stream.recipient, now);
+            if newest.is_some_and(|newest| newest.stream_id != stream.stream_id) {
+                continue;
+            }
+
+            info!("⛽ Stream #{} runs dry in {}s; opening a replacement", stream.stream_id, stream.depletes_at(now) - now);
+            let requirement = X402PaymentRequirement {
+                recipient: stream.recipient,
+                amount: None,
+                mode: PaymentMode::Streaming,
+                rate_per_second: Some(stream.rate_per_second),
+                min_deposit: Some(stream.deposit),
+                description: Some(format!("Top-up of stream #{}", stream.stream_id)),
+                network: None,
+                token: None,
+                contract: None,
+                scheme: None,
+                dialect: stream.dialect,
+            };
+            match self.trigger_payment(&stream.service_url, &requirement).await {
+                Ok(proof) => {
+                    self.stats.streams_topped_up.fetch_add(1, Ordering::Relaxed);
+                    if let Some(replacement) = proof.stream_id.and_then(|id| self.streams.by_id(id, now)) {
+                        info!("   └─ Stream #{} replaces #{}", replacement.stream_id, stream.stream_id);
+                        replacements.push(replacement);
+                    }
+                }
+                Err(e) => warn!("⚠️ Could not top up stream #{}: {}", stream.stream_id, e),
+            }
+        }
+        replacements
+    }
+
+    /// Run [`Self::top_up_streams`] every `period` in the background.
+    /// The task ends once the agent is dropped.
+    pub fn spawn_top_up_monitor(self: &Arc<Self>, period: Duration) -> tokio::task::JoinHandle<()> {
+        let agent: Weak<Self> = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(period);
+            ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                ticks.tick().await;
+                let Some(agent) = agent.upgrade() else {
+                    break;
+                };
+                agent.top_up_streams().await;
+            }
+        })
+    }
+
+    /// Backend paying from the service child address `sender`, if it is one
+    fn service_backend_for(&self, sender: Address) -> Result<Option<B>, ChainError> {
+        let Some(ref hd) = self.hd else {
+            return Ok(None);
+        };
+        let account = self.config.hd_index.unwrap_or(0);
+        let children: Vec<u32> = self.service_children.lock().unwrap().values().copied().collect();
+        for child in children {
+            let wallet = hd
+                .service_child(account, child)
+                .map_err(|e| ChainError::Config(e.to_string()))?;
+            if wallet.address() == sender {
+                return Ok(Some(self.backend.with_wallet(&wallet)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Hold budget for a payment and its largest possible gas fee; released
+    /// again if the payment fails
+    fn reserve(&self, requirement: &X402PaymentRequirement, spend: Amount) -> Result<Reservation<'_>, PaymentError> {
+        self.budget.reserve(spend).map_err(|exceeded| {
+            warn!("🛑 {}", exceeded);
+            PaymentError::BudgetExceeded {
+                requirement: Box::new(requirement.clone()),
+                spend: exceeded.spend,
+                remaining: exceeded.remaining,
+            }
+        })
+    }
+
+    /// The agent's spending limit
+    pub fn budget(&self) -> &Budget {
+        &self.budget
+    }
+
+    /// Count a settled payment and add it, gas included, to the running
+    /// total, whether or not the service goes on to accept it
+    fn record_payment(&self, amount: Amount) {
+        self.stats.payments_made.fetch_add(1, Ordering::Relaxed);
+        self.record_spend(amount);
+    }
+
+    /// Add `amount` to the running total without counting a payment
+    fn record_spend(&self, amount: Amount) {
+        let mut total = self.stats.total_spent.lock().unwrap();
+        *total = total.saturating_add(amount);
+    }
+
+    /// Get total amount spent
+    pub fn total_spent(&self) -> Amount {
+        *self.stats.total_spent.lock().unwrap()
     }
 
     /// Display agent stats
     pub fn display_stats(&self) {
-        info!("📊 Agent Stats:");
+        info!("📊 Agent Stats{}:", if self.config.dry_run { " (dry run)" } else { "" });
         info!("   ├─ Requests: {}", self.stats.requests_made.load(Ordering::Relaxed));
         info!("   ├─ Payments: {}", self.stats.payments_made.load(Ordering::Relaxed));
-        info!("   ├─ Spent: {:.6} TCRO", self.total_spent());
-        info!("   └─ Active Streams: {}", self.stats.active_streams.load(Ordering::Relaxed));
+        info!("   ├─ Spent: {}", self.total_spent().with_symbol("TCRO"));
+        info!("   ├─ Refunded: {}", self.stats.total_refunded.lock().unwrap().with_symbol("TCRO"));
+        info!(
+            "   ├─ Reclaimed from Idle Streams: {} ({} cancelled)",
+            self.stats.idle_reclaimed.lock().unwrap().with_symbol("TCRO"),
+            self.stats.idle_streams_cancelled.load(Ordering::Relaxed)
+        );
+        info!("   ├─ Streams Topped Up: {}", self.stats.streams_topped_up.load(Ordering::Relaxed));
+        info!("   ├─ Budget Left: {}", self.budget.remaining().with_symbol("TCRO"));
+        info!("   └─ Active Streams: {}", self.active_streams().len());
     }
 
     /// Use Gemini to decide whether to make a payment
@@ -256,17 +1108,17 @@ Service: {}
 Payment Mode: {:?}
 Cost: {} TCRO (rate: {} /sec)
 Your Budget: {} TCRO
-Already Spent: {:.4} TCRO
+Already Spent This Period: {} TCRO
 
 Context: {}
 
 Respond with just YES or NO."#,
             requirement.description.clone().unwrap_or_else(|| "API Service".to_string()),
             requirement.mode,
-            requirement.amount.clone().or(requirement.min_deposit.clone()).unwrap_or("unknown".to_string()),
-            requirement.rate_per_second.clone().unwrap_or("N/A".to_string()),
-            self.config.daily_budget,
-            self.total_spent(),
+            requirement.amount.or(requirement.min_deposit).map(|a| a.to_string()).unwrap_or("unknown".to_string()),
+            requirement.rate_per_second.map(|r| r.to_string()).unwrap_or("N/A".to_string()),
+            self.budget.limit(),
+            self.budget.spent(),
             context
         );
 
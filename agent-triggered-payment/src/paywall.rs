@@ -0,0 +1,631 @@
+//! Server-side x402 for axum and tower services, the Rust counterpart of
+//! `payStreamMiddleware.js`. [`PaywallLayer`] prices routes, answers unpaid
+//! requests with a PayStream-dialect 402, and passes proofs to a
+//! [`ProofVerifier`], such as [`BackendVerifier`]. Accepted requests reach
+//! the inner service with a [`VerifiedPayment`] in their extensions.
+//! Enabled by the `paywall` feature.
+
+use async_trait::async_trait;
+use axum::http::header::CONTENT_TYPE;
+use axum::http::{HeaderValue, Request, StatusCode};
+use axum::response::{IntoResponse, Response};
+use ethers::core::types::H256;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tower_layer::Layer;
+use tower_service::Service;
+use tracing::{info, warn};
+
+use crate::address::Address;
+use crate::amount::Amount;
+use crate::backend::{PaymentBackend, StreamState};
+use crate::chain::ChainError;
+use crate::x402::{HeaderDialect, PaymentMode, PaymentProof, X402Error, X402PaymentRequirement};
+
+/// Minimum deposit advertised for streaming routes that set none, as in
+/// `payStreamMiddleware.js`
+pub const DEFAULT_MIN_DEPOSIT: Amount = Amount::from_wei_u128(1_000_000_000_000_000); // 0.001 TCRO
+
+/// Which request paths a price applies to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RouteMatch {
+    Exact(String),
+    Prefix(String),
+}
+
+impl RouteMatch {
+    pub fn matches(&self, path: &str) -> bool {
+        match self {
+            RouteMatch::Exact(exact) => path == exact,
+            RouteMatch::Prefix(prefix) => path.starts_with(prefix.as_str()),
+        }
+    }
+}
+
+/// Price of the paths one [`RouteMatch`] covers
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoutePrice {
+    pub matcher: RouteMatch,
+    pub mode: PaymentMode,
+    /// Per second when streaming, per call otherwise
+    pub price: Amount,
+    pub min_deposit: Option<Amount>,
+    pub description: Option<String>,
+}
+
+impl RoutePrice {
+    /// Price for exactly `path`
+    pub fn exact(path: &str, mode: PaymentMode, price: Amount) -> Self {
+        Self::new(RouteMatch::Exact(path.to_string()), mode, price)
+    }
+
+    /// Price for every path starting with `prefix`
+    pub fn prefix(prefix: &str, mode: PaymentMode, price: Amount) -> Self {
+        Self::new(RouteMatch::Prefix(prefix.to_string()), mode, price)
+    }
+
+    pub fn new(matcher: RouteMatch, mode: PaymentMode, price: Amount) -> Self {
+        Self {
+            matcher,
+            mode,
+            price,
+            min_deposit: None,
+            description: None,
+        }
+    }
+
+    pub fn with_min_deposit(mut self, min_deposit: Amount) -> Self {
+        self.min_deposit = Some(min_deposit);
+        self
+    }
+
+    pub fn with_description(mut self, description: &str) -> Self {
+        self.description = Some(description.to_string());
+        self
+    }
+}
+
+/// Who gets paid, and what each route costs
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaywallConfig {
+    pub recipient: Address,
+    /// PayStreamStream contract advertised in challenges
+    pub contract: Option<Address>,
+    pub routes: Vec<RoutePrice>,
+}
+
+impl PaywallConfig {
+    pub fn new(recipient: Address) -> Self {
+        Self {
+            recipient,
+            contract: None,
+            routes: Vec::new(),
+        }
+    }
+
+    pub fn with_contract(mut self, contract: Address) -> Self {
+        self.contract = Some(contract);
+        self
+    }
+
+    pub fn with_route(mut self, route: RoutePrice) -> Self {
+        self.routes.push(route);
+        self
+    }
+
+    /// Price for `path`: an exact match, else the longest matching prefix.
+    /// Unpriced paths are free.
+    pub fn route_for(&self, path: &str) -> Option<&RoutePrice> {
+        self.routes
+            .iter()
+            .find(|route| matches!(route.matcher, RouteMatch::Exact(_)) && route.matcher.matches(path))
+            .or_else(|| {
+                self.routes
+                    .iter()
+                    .filter(|route| route.matcher.matches(path))
+                    .filter_map(|route| match &route.matcher {
+                        RouteMatch::Prefix(prefix) => Some((prefix.len(), route)),
+                        RouteMatch::Exact(_) => None,
+                    })
+                    .max_by_key(|(len, _)| *len)
+                    .map(|(_, route)| route)
+            })
+    }
+
+    /// The 402 requirement for a route, in the PayStream dialect
+    pub fn requirement(&self, route: &RoutePrice) -> X402PaymentRequirement {
+        let (amount, rate_per_second, min_deposit) = match route.mode {
+            PaymentMode::Streaming => (
+                None,
+                Some(route.price),
+                Some(route.min_deposit.unwrap_or(DEFAULT_MIN_DEPOSIT)),
+            ),
+            PaymentMode::PerRequest => (Some(route.price), None, route.min_deposit),
+        };
+        X402PaymentRequirement {
+            recipient: self.recipient,
+            amount,
+            mode: route.mode.clone(),
+            rate_per_second,
+            min_deposit,
+            description: route.description.clone(),
+            network: None,
+            token: Some("TCRO".to_string()),
+            contract: self.contract,
+            scheme: None,
+            dialect: HeaderDialect::PayStream,
+        }
+    }
+}
+
+/// A payment the verifier accepted, attached to the request's extensions
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifiedPayment {
+    pub mode: PaymentMode,
+    pub stream_id: Option<u64>,
+    pub tx_hash: Option<String>,
+    pub payer: Address,
+    /// The transfer's value, or the stream's deposit
+    pub amount: Amount,
+}
+
+/// Why a proof was refused
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum Rejection {
+    #[error("proof headers are not valid: {0}")]
+    InvalidProof(X402Error),
+    /// A stream proof on a per-request route, or the other way round
+    #[error("route takes {expected:?} payments")]
+    WrongMode { expected: PaymentMode },
+    /// Proof headers in another dialect than the route's challenge
+    #[error("route takes {expected:?} proof headers, got {got:?}")]
+    WrongDialect { expected: HeaderDialect, got: HeaderDialect },
+    #[error("stream {0} does not exist")]
+    UnknownStream(u64),
+    #[error("stream {0} has been cancelled")]
+    InactiveStream(u64),
+    #[error("stream {0} is past its stop time")]
+    ExpiredStream(u64),
+    /// Too little is left unstreamed to pay for the request
+    #[error("stream has {remaining} TCRO left, needs {needed} TCRO")]
+    InsufficientBalance { remaining: Amount, needed: Amount },
+    #[error("payment goes to {0}, not this service")]
+    WrongRecipient(Address),
+    #[error("stream flows {flow_rate} TCRO/sec, below the route's {rate} TCRO/sec")]
+    RateTooLow { flow_rate: Amount, rate: Amount },
+    #[error("transaction {0} is not a known transfer")]
+    UnknownTransaction(String),
+    #[error("paid {paid} TCRO, below the route's {price} TCRO")]
+    Underpaid { paid: Amount, price: Amount },
+    #[error("transaction {0} already paid for a request")]
+    Replayed(String),
+    /// The payment could not be looked up; it may still be valid
+    #[error("could not check the payment: {0}")]
+    Unavailable(String),
+}
+
+/// Checks a proof against wherever payments settle
+#[async_trait]
+pub trait ProofVerifier: Send + Sync + 'static {
+    async fn verify(
+        &self,
+        requirement: &X402PaymentRequirement,
+        proof: &PaymentProof,
+    ) -> Result<VerifiedPayment, Rejection>;
+}
+
+/// How long [`StreamVerifier`] reuses a `streams(id)` read
+pub const DEFAULT_STREAM_CACHE_TTL: Duration = Duration::from_secs(5);
+
+/// Verifies stream proofs by reading `streams(id)` and its claimable balance
+/// from a [`PaymentBackend`], rather than trusting `isStreamActive` alone.
+/// A stream is accepted when it is active, pays this service's recipient,
+/// flows at least the route's rate, holds at least the minimum deposit, and
+/// has enough unstreamed balance left before `stopTime` to cover
+/// `min_remaining_secs` more seconds at that rate.
+///
+/// Reads are cached for a short time; the claimable balance of a cached
+/// stream is advanced to the current time with the contract's formula.
+/// Per-request proofs are refused with [`Rejection::WrongMode`].
+pub struct StreamVerifier<B> {
+    backend: B,
+    cache_ttl: Duration,
+    min_remaining_secs: u64,
+    cache: Mutex<HashMap<u64, (Instant, StreamState)>>,
+}
+
+impl<B: PaymentBackend> StreamVerifier<B> {
+    pub fn new(backend: B) -> Self {
+        Self {
+            backend,
+            cache_ttl: DEFAULT_STREAM_CACHE_TTL,
+            min_remaining_secs: 1,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Reuse reads for `ttl`; zero reads the stream on every request
+    pub fn with_cache_ttl(mut self, ttl: Duration) -> Self {
+        self.cache_ttl = ttl;
+        self
+    }
+
+    /// Seconds of service at the route's rate the stream must still hold
+    pub fn with_min_remaining_secs(mut self, secs: u64) -> Self {
+        self.min_remaining_secs = secs;
+        self
+    }
+
+    /// `streams(id)`, from the cache while it is fresh
+    async fn read(&self, stream_id: u64) -> Result<StreamState, Rejection> {
+        if let Some((read_at, state)) = self.cache.lock().unwrap().get(&stream_id) {
+            if read_at.elapsed() < self.cache_ttl {
+                return Ok(state.clone());
+            }
+        }
+
+        let state = self.backend.stream(stream_id).await.map_err(|e| match e {
+            ChainError::UnknownStream(id) => Rejection::UnknownStream(id),
+            other => Rejection::Unavailable(other.to_string()),
+        })?;
+        if !self.cache_ttl.is_zero() {
+            let mut cache = self.cache.lock().unwrap();
+            cache.retain(|_, (read_at, _)| read_at.elapsed() < self.cache_ttl);
+            cache.insert(stream_id, (Instant::now(), state.clone()));
+        }
+        Ok(state)
+    }
+}
+
+impl<B> fmt::Debug for StreamVerifier<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StreamVerifier")
+            .field("cache_ttl", &self.cache_ttl)
+            .field("min_remaining_secs", &self.min_remaining_secs)
+            .finish_non_exhaustive()
+    }
+}
+
+#[async_trait]
+impl<B: PaymentBackend> ProofVerifier for StreamVerifier<B> {
+    async fn verify(
+        &self,
+        requirement: &X402PaymentRequirement,
+        proof: &PaymentProof,
+    ) -> Result<VerifiedPayment, Rejection> {
+        let stream_id = match (&requirement.mode, proof.stream_id) {
+            (PaymentMode::Streaming, Some(stream_id)) => stream_id,
+            (expected, _) => {
+                return Err(Rejection::WrongMode {
+                    expected: expected.clone(),
+                })
+            }
+        };
+
+        let stream = self.read(stream_id).await?;
+        if !stream.is_active {
+            return Err(Rejection::InactiveStream(stream_id));
+        }
+        if stream.recipient != requirement.recipient {
+            return Err(Rejection::WrongRecipient(stream.recipient));
+        }
+        let rate = requirement.rate_per_second.unwrap_or(Amount::ZERO);
+        if stream.flow_rate < rate {
+            return Err(Rejection::RateTooLow {
+                flow_rate: stream.flow_rate,
+                rate,
+            });
+        }
+        let min_deposit = requirement.min_deposit.unwrap_or(Amount::ZERO);
+        if stream.total_amount < min_deposit {
+            return Err(Rejection::Underpaid {
+                paid: stream.total_amount,
+                price: min_deposit,
+            });
+        }
+
+        let now = self.backend.now();
+        if now >= stream.stop_time {
+            return Err(Rejection::ExpiredStream(stream_id));
+        }
+        let claimable = stream.claimable.max(stream.claimable_at(now));
+        let remaining = stream
+            .total_amount
+            .saturating_sub(stream.amount_withdrawn)
+            .saturating_sub(claimable);
+        let needed = Amount::from_wei(rate.wei().saturating_mul(self.min_remaining_secs.into()));
+        if stream.stop_time - now < self.min_remaining_secs || remaining < needed {
+            return Err(Rejection::InsufficientBalance { remaining, needed });
+        }
+
+        Ok(VerifiedPayment {
+            mode: PaymentMode::Streaming,
+            stream_id: Some(stream_id),
+            tx_hash: None,
+            payer: stream.sender,
+            amount: stream.total_amount,
+        })
+    }
+}
+
+/// Verifies per-request proofs by looking the transaction up through a
+/// [`PaymentBackend`]. A transfer is accepted when it is a successful native
+/// transfer to this service's recipient for at least the route's price, and
+/// has not paid for a request before.
+///
+/// Spent hashes are kept in memory for the verifier's lifetime. A restarted
+/// service forgets them, so seed the set with [`Self::with_spent`] from
+/// wherever the service records [`VerifiedPayment`]s.
+/// Stream proofs are refused with [`Rejection::WrongMode`].
+pub struct TransferVerifier<B> {
+    backend: B,
+    /// Transaction hashes that already paid for a request
+    spent: Mutex<HashSet<H256>>,
+}
+
+impl<B: PaymentBackend> TransferVerifier<B> {
+    pub fn new(backend: B) -> Self {
+        Self {
+            backend,
+            spent: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Treat `tx_hashes` as already spent
+    pub fn with_spent(self, tx_hashes: impl IntoIterator<Item = H256>) -> Self {
+        self.spent.lock().unwrap().extend(tx_hashes);
+        self
+    }
+}
+
+impl<B> fmt::Debug for TransferVerifier<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TransferVerifier")
+            .field("spent", &self.spent.lock().unwrap().len())
+            .finish_non_exhaustive()
+    }
+}
+
+#[async_trait]
+impl<B: PaymentBackend> ProofVerifier for TransferVerifier<B> {
+    async fn verify(
+        &self,
+        requirement: &X402PaymentRequirement,
+        proof: &PaymentProof,
+    ) -> Result<VerifiedPayment, Rejection> {
+        let tx_hash = match (&requirement.mode, proof.tx_hash.as_deref()) {
+            (PaymentMode::PerRequest, Some(tx_hash)) => tx_hash,
+            (expected, _) => {
+                return Err(Rejection::WrongMode {
+                    expected: expected.clone(),
+                })
+            }
+        };
+
+        let unknown = || Rejection::UnknownTransaction(tx_hash.to_string());
+        let hash: H256 = tx_hash.parse().map_err(|_| unknown())?;
+        if self.spent.lock().unwrap().contains(&hash) {
+            return Err(Rejection::Replayed(tx_hash.to_string()));
+        }
+        let transfer = self
+            .backend
+            .find_transfer(hash)
+            .await
+            .map_err(|e| Rejection::Unavailable(e.to_string()))?
+            .ok_or_else(unknown)?;
+        if transfer.to != requirement.recipient {
+            return Err(Rejection::WrongRecipient(transfer.to));
+        }
+        let price = requirement.amount.unwrap_or(Amount::ZERO);
+        if transfer.amount < price {
+            return Err(Rejection::Underpaid {
+                paid: transfer.amount,
+                price,
+            });
+        }
+        // Checked again: another request may have spent it during the lookup
+        if !self.spent.lock().unwrap().insert(hash) {
+            return Err(Rejection::Replayed(tx_hash.to_string()));
+        }
+
+        Ok(VerifiedPayment {
+            mode: PaymentMode::PerRequest,
+            stream_id: None,
+            tx_hash: Some(tx_hash.to_string()),
+            payer: transfer.from,
+            amount: transfer.amount,
+        })
+    }
+}
+
+/// Verifies both kinds of proof against one [`PaymentBackend`]: streams as
+/// [`StreamVerifier`] does, transfers as [`TransferVerifier`] does. The
+/// route's mode decides which applies.
+pub struct BackendVerifier<B> {
+    streams: StreamVerifier<B>,
+    transfers: TransferVerifier<B>,
+}
+
+impl<B: PaymentBackend + Clone> BackendVerifier<B> {
+    pub fn new(backend: B) -> Self {
+        Self {
+            streams: StreamVerifier::new(backend.clone()),
+            transfers: TransferVerifier::new(backend),
+        }
+    }
+}
+
+impl<B: PaymentBackend> BackendVerifier<B> {
+    /// See [`StreamVerifier::with_cache_ttl`]
+    pub fn with_cache_ttl(mut self, ttl: Duration) -> Self {
+        self.streams = self.streams.with_cache_ttl(ttl);
+        self
+    }
+
+    /// See [`StreamVerifier::with_min_remaining_secs`]
+    pub fn with_min_remaining_secs(mut self, secs: u64) -> Self {
+        self.streams = self.streams.with_min_remaining_secs(secs);
+        self
+    }
+
+    /// See [`TransferVerifier::with_spent`]
+    pub fn with_spent(mut self, tx_hashes: impl IntoIterator<Item = H256>) -> Self {
+        self.transfers = self.transfers.with_spent(tx_hashes);
+        self
+    }
+}
+
+impl<B> fmt::Debug for BackendVerifier<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BackendVerifier")
+            .field("streams", &self.streams)
+            .field("transfers", &self.transfers)
+            .finish()
+    }
+}
+
+#[async_trait]
+impl<B: PaymentBackend> ProofVerifier for BackendVerifier<B> {
+    async fn verify(
+        &self,
+        requirement: &X402PaymentRequirement,
+        proof: &PaymentProof,
+    ) -> Result<VerifiedPayment, Rejection> {
+        match requirement.mode {
+            PaymentMode::Streaming => self.streams.verify(requirement, proof).await,
+            PaymentMode::PerRequest => self.transfers.verify(requirement, proof).await,
+        }
+    }
+}
+
+/// Tower layer putting priced routes behind x402
+#[derive(Debug)]
+pub struct PaywallLayer<V> {
+    config: Arc<PaywallConfig>,
+    verifier: Arc<V>,
+}
+
+impl<V: ProofVerifier> PaywallLayer<V> {
+    pub fn new(config: PaywallConfig, verifier: V) -> Self {
+        Self {
+            config: Arc::new(config),
+            verifier: Arc::new(verifier),
+        }
+    }
+}
+
+impl<V> Clone for PaywallLayer<V> {
+    fn clone(&self) -> Self {
+        Self {
+            config: self.config.clone(),
+            verifier: self.verifier.clone(),
+        }
+    }
+}
+
+impl<S, V> Layer<S> for PaywallLayer<V> {
+    type Service = Paywall<S, V>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Paywall {
+            inner,
+            config: self.config.clone(),
+            verifier: self.verifier.clone(),
+        }
+    }
+}
+
+/// Service produced by [`PaywallLayer`]
+#[derive(Debug)]
+pub struct Paywall<S, V> {
+    inner: S,
+    config: Arc<PaywallConfig>,
+    verifier: Arc<V>,
+}
+
+impl<S: Clone, V> Clone for Paywall<S, V> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            config: self.config.clone(),
+            verifier: self.verifier.clone(),
+        }
+    }
+}
+
+impl<S, V, B> Service<Request<B>> for Paywall<S, V>
+where
+    S: Service<Request<B>, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    V: ProofVerifier,
+    B: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<B>) -> Self::Future {
+        // The clone may not be ready; call the one that was polled
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let config = self.config.clone();
+        let verifier = self.verifier.clone();
+
+        Box::pin(async move {
+            let path = request.uri().path().to_string();
+            let Some(route) = config.route_for(&path) else {
+                return inner.call(request).await;
+            };
+            let requirement = config.requirement(route);
+
+            let proof = match PaymentProof::from_header_map(request.headers()) {
+                Ok(Some(proof)) => proof,
+                Ok(None) => return Ok(challenge(&requirement)),
+                Err(e) => {
+                    warn!("[PayStream] Refused proof for {}: {}", path, e);
+                    return Ok(challenge(&requirement));
+                }
+            };
+
+            match verifier.verify(&requirement, &proof).await {
+                Ok(payment) => {
+                    info!("[PayStream] Request accepted for {} ({:?})", path, payment.mode);
+                    request.extensions_mut().insert(payment);
+                    inner.call(request).await
+                }
+                Err(Rejection::Unavailable(reason)) => {
+                    warn!("[PayStream] Could not verify payment for {}: {}", path, reason);
+                    Ok(StatusCode::SERVICE_UNAVAILABLE.into_response())
+                }
+                Err(rejection) => {
+                    warn!("[PayStream] Refused proof for {}: {}", path, rejection);
+                    Ok(challenge(&requirement))
+                }
+            }
+        })
+    }
+}
+
+/// 402 with the requirement as headers and JSON body
+pub fn challenge(requirement: &X402PaymentRequirement) -> Response {
+    match requirement.to_headers(requirement.dialect) {
+        Ok(mut headers) => {
+            headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+            (StatusCode::PAYMENT_REQUIRED, headers, requirement.to_json_body()).into_response()
+        }
+        Err(e) => {
+            warn!("[PayStream] Cannot express challenge as headers: {}", e);
+            (StatusCode::PAYMENT_REQUIRED, requirement.to_json_body()).into_response()
+        }
+    }
+}
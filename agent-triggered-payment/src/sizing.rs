@@ -0,0 +1,217 @@
+//! Stream sizing. A 402 challenge advertises a per-second rate and a minimum
+//! deposit, while `createStream` takes a deposit and a duration and derives
+//! `flowRate = deposit / duration` with floor division. This module turns a
+//! challenge into arguments the contract accepts and whose derived rate pays
+//! at least what the service asked for.
+
+use ethers::core::types::U256;
+use thiserror::Error;
+
+use crate::address::Address;
+use crate::amount::Amount;
+use crate::x402::{X402PaymentRequirement, DEFAULT_DEPOSIT, DEFAULT_RATE};
+
+/// A stream the contract would refuse, or one that would underpay
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum SizingError {
+    #[error("stream recipient is the zero address")]
+    ZeroRecipient,
+    #[error("stream deposit is zero")]
+    ZeroDeposit,
+    #[error("advertised rate is zero")]
+    ZeroRate,
+    #[error("stream duration is zero")]
+    ZeroDuration,
+    /// The deposit does not cover a single second at the advertised rate
+    #[error("deposit of {deposit} TCRO is below one second at {rate} TCRO/sec")]
+    DepositBelowRate { deposit: Amount, rate: Amount },
+    #[error("duration of {0} seconds does not fit in a u64")]
+    DurationOverflow(U256),
+    /// `deposit / duration` rounds down to zero, which the contract rejects
+    #[error("flow rate of {deposit} TCRO over {duration}s rounds to zero")]
+    ZeroFlowRate { deposit: Amount, duration: u64 },
+    #[error("derived flow rate of {derived} TCRO/sec is below the advertised {advertised} TCRO/sec")]
+    FlowRateBelowAdvertised { derived: Amount, advertised: Amount },
+}
+
+/// Arguments for `createStream` and the flow rate the contract will derive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamSize {
+    pub deposit: Amount,
+    pub duration_secs: u64,
+    /// `deposit / duration_secs`, floored, as stored on chain
+    pub flow_rate: Amount,
+}
+
+impl StreamSize {
+    /// Size a stream for a requirement, falling back to the default deposit
+    /// and rate when the challenge omits them. The duration is the longest
+    /// whose derived flow rate still meets the advertised rate.
+    pub fn for_requirement(requirement: &X402PaymentRequirement) -> Result<Self, SizingError> {
+        let deposit = requirement.min_deposit.unwrap_or(DEFAULT_DEPOSIT);
+        let rate = requirement.rate_per_second.unwrap_or(DEFAULT_RATE);
+        let size = Self::for_rate(deposit, rate)?;
+        validate(requirement.recipient, size.deposit, size.duration_secs, Some(rate))?;
+        Ok(size)
+    }
+
+    /// Longest stream of `deposit` paying at least `rate` per second
+    pub fn for_rate(deposit: Amount, rate: Amount) -> Result<Self, SizingError> {
+        if deposit.is_zero() {
+            return Err(SizingError::ZeroDeposit);
+        }
+        if rate.is_zero() {
+            return Err(SizingError::ZeroRate);
+        }
+        // floor(deposit / floor(deposit / rate)) >= rate, so rounding never underpays
+        let duration = deposit.wei() / rate.wei();
+        if duration.is_zero() {
+            return Err(SizingError::DepositBelowRate { deposit, rate });
+        }
+        if duration > U256::from(u64::MAX) {
+            return Err(SizingError::DurationOverflow(duration));
+        }
+        Self::new(deposit, duration.as_u64())
+    }
+
+    /// A stream of `deposit` over `duration_secs`, checked against the
+    /// contract's rules
+    pub fn new(deposit: Amount, duration_secs: u64) -> Result<Self, SizingError> {
+        if deposit.is_zero() {
+            return Err(SizingError::ZeroDeposit);
+        }
+        if duration_secs == 0 {
+            return Err(SizingError::ZeroDuration);
+        }
+        let flow_rate = Amount::from_wei(deposit.wei() / duration_secs);
+        if flow_rate.is_zero() {
+            return Err(SizingError::ZeroFlowRate { deposit, duration: duration_secs });
+        }
+        Ok(Self {
+            deposit,
+            duration_secs,
+            flow_rate,
+        })
+    }
+}
+
+/// Check `createStream(recipient, duration)` with `deposit` attached against
+/// the contract's `require`s and, when given, the service's advertised rate
+pub fn validate(
+    recipient: Address,
+    deposit: Amount,
+    duration_secs: u64,
+    advertised_rate: Option<Amount>,
+) -> Result<StreamSize, SizingError> {
+    if recipient.is_zero() {
+        return Err(SizingError::ZeroRecipient);
+    }
+    let size = StreamSize::new(deposit, duration_secs)?;
+    if let Some(advertised) = advertised_rate {
+        if size.flow_rate < advertised {
+            return Err(SizingError::FlowRateBelowAdvertised {
+                derived: size.flow_rate,
+                advertised,
+            });
+        }
+    }
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::x402::{HeaderDialect, PaymentMode};
+
+    fn wei(wei: u128) -> Amount {
+        Amount::from_wei_u128(wei)
+    }
+
+    #[test]
+    fn floors_duration_and_rate_without_underpaying() {
+        // 1000 / 3 = 333s, and 1000 / 333 floors to 3 wei/sec
+        assert_eq!(
+            StreamSize::for_rate(wei(1000), wei(3)),
+            Ok(StreamSize {
+                deposit: wei(1000),
+                duration_secs: 333,
+                flow_rate: wei(3),
+            })
+        );
+        // 1000 / 7 = 142s, and 1000 / 142 floors to 7 wei/sec
+        assert_eq!(StreamSize::for_rate(wei(1000), wei(7)).unwrap().flow_rate, wei(7));
+        assert_eq!(StreamSize::new(wei(1000), 3).unwrap().flow_rate, wei(333));
+    }
+
+    #[test]
+    fn rejects_zero_rate_deposit_and_duration() {
+        assert_eq!(StreamSize::for_rate(wei(1000), Amount::ZERO), Err(SizingError::ZeroRate));
+        assert_eq!(StreamSize::for_rate(Amount::ZERO, wei(1)), Err(SizingError::ZeroDeposit));
+        assert_eq!(StreamSize::new(wei(1000), 0), Err(SizingError::ZeroDuration));
+        assert_eq!(StreamSize::new(Amount::ZERO, 60), Err(SizingError::ZeroDeposit));
+    }
+
+    #[test]
+    fn rejects_streams_the_contract_would_refuse() {
+        assert_eq!(
+            StreamSize::for_rate(wei(5), wei(10)),
+            Err(SizingError::DepositBelowRate {
+                deposit: wei(5),
+                rate: wei(10),
+            })
+        );
+        assert_eq!(
+            StreamSize::new(wei(10), 20),
+            Err(SizingError::ZeroFlowRate {
+                deposit: wei(10),
+                duration: 20,
+            })
+        );
+        let huge = Amount::from_wei(U256::from(u64::MAX) + 1);
+        assert_eq!(
+            StreamSize::for_rate(huge, wei(1)),
+            Err(SizingError::DurationOverflow(U256::from(u64::MAX) + 1))
+        );
+    }
+
+    #[test]
+    fn validate_checks_recipient_and_advertised_rate() {
+        let recipient: Address = "0x70997970C51812dc3A010C7d01b50e0d17dc79C8".parse().unwrap();
+        assert_eq!(validate(Address::ZERO, wei(1000), 333, None), Err(SizingError::ZeroRecipient));
+        assert!(validate(recipient, wei(1000), 333, Some(wei(3))).is_ok());
+        assert_eq!(
+            validate(recipient, wei(1000), 333, Some(wei(4))),
+            Err(SizingError::FlowRateBelowAdvertised {
+                derived: wei(3),
+                advertised: wei(4),
+            })
+        );
+    }
+
+    #[test]
+    fn sizes_a_challenge_with_defaults_for_missing_fields() {
+        let requirement = X402PaymentRequirement {
+            recipient: "0x70997970C51812dc3A010C7d01b50e0d17dc79C8".parse().unwrap(),
+            amount: None,
+            mode: PaymentMode::Streaming,
+            rate_per_second: Some("0.0001".parse().unwrap()),
+            min_deposit: Some("0.36".parse().unwrap()),
+            description: None,
+            network: None,
+            token: None,
+            contract: None,
+            scheme: None,
+            dialect: HeaderDialect::PayStream,
+        };
+        let size = StreamSize::for_requirement(&requirement).unwrap();
+        assert_eq!((size.duration_secs, size.flow_rate), (3600, "0.0001".parse().unwrap()));
+
+        let bare = X402PaymentRequirement {
+            rate_per_second: None,
+            min_deposit: None,
+            ..requirement
+        };
+        let size = StreamSize::for_requirement(&bare).unwrap();
+        assert_eq!((size.deposit, size.flow_rate), (DEFAULT_DEPOSIT, DEFAULT_RATE));
+    }
+}
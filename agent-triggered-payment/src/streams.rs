@@ -0,0 +1,191 @@
+//! Open payment streams, so later requests to the same service reuse a
+//! stream instead of paying again.
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::address::Address;
+use crate::amount::Amount;
+use crate::budget::SpendId;
+use crate::x402::{HeaderDialect, PaymentProof};
+
+/// Streams this close to their stop time are not reused, so a request is
+/// not sent on a stream that runs dry in flight
+pub const EXPIRY_MARGIN_SECS: u64 = 5;
+
+/// An open stream paying one recipient for one host
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StreamInfo {
+    pub stream_id: u64,
+    /// Address that opened the stream and receives its refund
+    pub sender: Address,
+    pub recipient: Address,
+    /// `host[:port]` of the service the stream pays for
+    pub host: String,
+    /// URL of the request that opened the stream
+    pub service_url: String,
+    pub deposit: Amount,
+    pub rate_per_second: Amount,
+    /// Unix seconds
+    pub start_time: u64,
+    /// Unix seconds; the contract's `stopTime`
+    pub expires_at: u64,
+    /// Dialect of the challenge that opened the stream
+    pub dialect: HeaderDialect,
+    /// Budget entry the deposit was charged to, for crediting refunds
+    pub spend: Option<SpendId>,
+    /// Unix seconds of the last request sent on the stream
+    pub last_used: u64,
+}
+
+impl StreamInfo {
+    pub fn is_expired_at(&self, now: u64) -> bool {
+        now >= self.expires_at
+    }
+
+    /// Whether no request has gone out on the stream for `idle_secs`
+    pub fn is_idle_at(&self, now: u64, idle_secs: u64) -> bool {
+        now.saturating_sub(self.last_used) >= idle_secs
+    }
+
+    /// Whether enough time is left to send another request on the stream
+    pub fn is_reusable_at(&self, now: u64) -> bool {
+        now + EXPIRY_MARGIN_SECS < self.expires_at
+    }
+
+    /// What `cancelStream` would return at `now` as (refund, payout),
+    /// mirroring the contract's floor-rate accounting
+    pub fn split_at(&self, now: u64) -> (Amount, Amount) {
+        if now >= self.expires_at {
+            return (Amount::ZERO, self.deposit);
+        }
+        let duration = self.expires_at.saturating_sub(self.start_time).max(1);
+        let flow_rate = self.deposit.wei() / duration;
+        let elapsed = now.saturating_sub(self.start_time);
+        let payout = Amount::from_wei(flow_rate.saturating_mul(elapsed.into())).min(self.deposit);
+        (self.deposit.saturating_sub(payout), payout)
+    }
+
+    /// When the balance left at `now` runs out at the stream's flow rate
+    pub fn depletes_at(&self, now: u64) -> u64 {
+        let (remaining, _) = self.split_at(now);
+        let duration = self.expires_at.saturating_sub(self.start_time).max(1);
+        let flow_rate = (self.deposit.wei() / duration).max(1.into());
+        let left = (remaining.wei() / flow_rate).min(u64::MAX.into()).as_u64();
+        now.saturating_add(left).min(self.expires_at)
+    }
+
+    /// Proof headers for a request on this stream. Nothing new is paid.
+    pub fn proof(&self) -> PaymentProof {
+        PaymentProof::streaming(self.stream_id, Amount::ZERO, self.dialect)
+    }
+}
+
+/// Streams the agent has open, by stream ID. A host can have several open
+/// to one recipient while a top-up replaces a stream running dry.
+#[derive(Debug, Default)]
+pub struct StreamRegistry {
+    streams: Mutex<HashMap<u64, StreamInfo>>,
+}
+
+impl StreamRegistry {
+    pub fn insert(&self, stream: StreamInfo) {
+        self.streams.lock().unwrap().insert(stream.stream_id, stream);
+    }
+
+    /// Stream to `recipient` for `host` with the most time left to reuse at `now`
+    pub fn get(&self, host: &str, recipient: Address, now: u64) -> Option<StreamInfo> {
+        self.live(now, |stream| stream.host == host && stream.recipient == recipient && stream.is_reusable_at(now))
+            .into_iter()
+            .max_by_key(|stream| stream.expires_at)
+    }
+
+    /// Reusable stream opened for `route` (see [`route_of`]) with the most
+    /// time left at `now`, to attach before the service has named a
+    /// recipient. Other routes on the host may be priced differently, so
+    /// their streams are only reused once a challenge names the recipient.
+    pub fn for_route(&self, route: &str, now: u64) -> Option<StreamInfo> {
+        self.live(now, |stream| route_of(&stream.service_url) == route && stream.is_reusable_at(now))
+            .into_iter()
+            .max_by_key(|stream| stream.expires_at)
+    }
+
+    /// Stream with the given ID, if still live at `now`
+    pub fn by_id(&self, stream_id: u64, now: u64) -> Option<StreamInfo> {
+        self.remove_expired(now);
+        self.streams.lock().unwrap().get(&stream_id).cloned()
+    }
+
+    pub fn remove_id(&self, stream_id: u64) -> Option<StreamInfo> {
+        self.streams.lock().unwrap().remove(&stream_id)
+    }
+
+    /// Record a request sent on the stream at `now`
+    pub fn touch(&self, stream_id: u64, now: u64) {
+        if let Some(stream) = self.streams.lock().unwrap().get_mut(&stream_id) {
+            stream.last_used = stream.last_used.max(now);
+        }
+    }
+
+    /// Streams live at `now` with no request for at least `idle_secs`
+    pub fn idle(&self, idle_secs: u64, now: u64) -> Vec<StreamInfo> {
+        self.live(now, |stream| stream.is_idle_at(now, idle_secs))
+    }
+
+    /// All streams live at `now`
+    pub fn active(&self, now: u64) -> Vec<StreamInfo> {
+        self.live(now, |_| true)
+    }
+
+    /// Drop streams past their stop time at `now` and return them
+    pub fn remove_expired(&self, now: u64) -> Vec<StreamInfo> {
+        let mut streams = self.streams.lock().unwrap();
+        let expired: Vec<_> = streams
+            .iter()
+            .filter(|(_, stream)| stream.is_expired_at(now))
+            .map(|(stream_id, _)| *stream_id)
+            .collect();
+        expired.into_iter().filter_map(|key| streams.remove(&key)).collect()
+    }
+
+    fn live(&self, now: u64, matches: impl Fn(&StreamInfo) -> bool) -> Vec<StreamInfo> {
+        self.remove_expired(now);
+        self.streams
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|stream| matches(stream))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Current Unix time in seconds, the default [`PaymentBackend::now`]
+///
+/// [`PaymentBackend::now`]: crate::backend::PaymentBackend::now
+pub fn now_secs() -> u64 {
+    Utc::now().timestamp().max(0) as u64
+}
+
+/// `host[:port]` of a URL, the key services are cached under
+pub fn host_of(url: &str) -> String {
+    match reqwest::Url::parse(url) {
+        Ok(parsed) => match (parsed.host_str(), parsed.port()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (Some(host), None) => host.to_string(),
+            (None, _) => url.to_string(),
+        },
+        Err(_) => url.to_string(),
+    }
+}
+
+/// `host[:port]/path` of a URL, without query or fragment: the resource a
+/// stream was opened to pay for
+pub fn route_of(url: &str) -> String {
+    match reqwest::Url::parse(url) {
+        Ok(parsed) => format!("{}{}", host_of(url), parsed.path()),
+        Err(_) => url.to_string(),
+    }
+}
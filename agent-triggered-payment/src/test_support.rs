@@ -0,0 +1,280 @@
+//! A local x402 service for exercising the challenge → pay → retry loop end
+//! to end. [`MockProvider`] serves paid routes that answer 402 in the FlowPay
+//! or PayStream dialect and check proof headers against a [`MemoryBackend`],
+//! the way a provider would check them against the chain. Also holds the
+//! accounts, agent and server fixtures the crate's integration tests share.
+//! Enabled by the `test-support` feature, which turns on `paywall` as well.
+
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use axum::Router;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+use crate::address::Address;
+use crate::amount::Amount;
+use crate::backend::{MemoryBackend, PaymentBackend};
+use crate::gemini::GeminiClient;
+use crate::payment_agent::{AgentConfig, PaymentAgent};
+use crate::paywall::{self, BackendVerifier, ProofVerifier, Rejection};
+use crate::x402::{HeaderDialect, PaymentMode, PaymentProof, X402PaymentRequirement};
+
+/// Hardhat/anvil default account #0, the paying side in tests
+pub const SENDER: &str = "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266";
+/// Hardhat/anvil default account #1, the provider side in tests
+pub const RECIPIENT: &str = "0x70997970C51812dc3A010C7d01b50e0d17dc79C8";
+
+/// Parse a literal TCRO amount
+pub fn tcro(value: &str) -> Amount {
+    value.parse().expect("valid TCRO amount")
+}
+
+/// [`RECIPIENT`] as an address
+pub fn recipient() -> Address {
+    RECIPIENT.parse().expect("valid address")
+}
+
+/// Ledger for [`SENDER`] holding 10 TCRO
+pub fn funded_backend() -> MemoryBackend {
+    let backend = MemoryBackend::new(SENDER.parse().expect("valid address"));
+    backend.credit(backend.sender(), tcro("10"));
+    backend
+}
+
+/// Config for an agent paying from `wallet_address` with a 5 TCRO budget
+pub fn agent_config(name: &str, wallet_address: Address) -> AgentConfig {
+    AgentConfig {
+        name: name.to_string(),
+        wallet_address,
+        daily_budget: Amount::from_whole(5),
+        ..AgentConfig::default()
+    }
+}
+
+/// Agent settling on `backend` as its sender, configured by [`agent_config`]
+pub fn agent(backend: MemoryBackend) -> PaymentAgent<MemoryBackend> {
+    agent_with(agent_config("test-agent", backend.sender()), backend)
+}
+
+/// Agent settling on any backend with the given config
+pub fn agent_with<B: PaymentBackend>(config: AgentConfig, backend: B) -> PaymentAgent<B> {
+    PaymentAgent::new(config, Arc::new(GeminiClient::new("test-key".to_string()))).with_backend(backend)
+}
+
+/// Serve `app` on an ephemeral port on 127.0.0.1 for the rest of the test
+pub async fn spawn_server(app: Router) -> SocketAddr {
+    let server = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(app.into_make_service());
+    let addr = server.local_addr();
+    tokio::spawn(server);
+    addr
+}
+
+/// A path that costs money, and how its 402 is expressed
+#[derive(Debug, Clone)]
+pub struct PaidRoute {
+    pub path: String,
+    /// Answered in its dialect; `Spec` is not served
+    pub requirement: X402PaymentRequirement,
+    /// Body returned once the request is paid for
+    pub body: String,
+}
+
+impl PaidRoute {
+    /// Route paid by a stream to `recipient` flowing at least `rate` per second
+    pub fn streaming(path: &str, recipient: Address, rate: Amount, min_deposit: Amount) -> Self {
+        Self::new(
+            path,
+            X402PaymentRequirement {
+                recipient,
+                amount: None,
+                mode: PaymentMode::Streaming,
+                rate_per_second: Some(rate),
+                min_deposit: Some(min_deposit),
+                description: None,
+                network: None,
+                token: None,
+                contract: None,
+                scheme: None,
+                dialect: HeaderDialect::PayStream,
+            },
+        )
+    }
+
+    /// Route paid by a transfer of at least `price` to `recipient` per request
+    pub fn per_request(path: &str, recipient: Address, price: Amount) -> Self {
+        Self::new(
+            path,
+            X402PaymentRequirement {
+                recipient,
+                amount: Some(price),
+                mode: PaymentMode::PerRequest,
+                rate_per_second: None,
+                min_deposit: None,
+                description: None,
+                network: None,
+                token: None,
+                contract: None,
+                scheme: None,
+                dialect: HeaderDialect::PayStream,
+            },
+        )
+    }
+
+    /// Route charging `requirement`, answered in its own dialect
+    pub fn new(path: &str, requirement: X402PaymentRequirement) -> Self {
+        Self {
+            path: path.to_string(),
+            requirement,
+            body: "paid".to_string(),
+        }
+    }
+
+    pub fn with_dialect(mut self, dialect: HeaderDialect) -> Self {
+        self.requirement.dialect = dialect;
+        self
+    }
+
+    pub fn with_body(mut self, body: &str) -> Self {
+        self.body = body.to_string();
+        self
+    }
+}
+
+/// Paid routes served over HTTP, verified against a [`MemoryBackend`]
+#[derive(Debug, Clone)]
+pub struct MockProvider {
+    backend: MemoryBackend,
+    routes: Vec<PaidRoute>,
+}
+
+impl MockProvider {
+    /// Provider checking proofs against `backend`'s ledger
+    pub fn new(backend: MemoryBackend) -> Self {
+        Self {
+            backend,
+            routes: Vec::new(),
+        }
+    }
+
+    pub fn with_route(mut self, route: PaidRoute) -> Self {
+        self.routes.push(route);
+        self
+    }
+
+    /// Serve on an ephemeral port on 127.0.0.1 until the handle is dropped
+    pub async fn start(self) -> RunningProvider {
+        let state = Arc::new(ProviderState {
+            verifier: BackendVerifier::new(self.backend).with_cache_ttl(Duration::ZERO),
+            routes: self.routes,
+            challenged: AtomicU64::new(0),
+            accepted: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+            rejections: Mutex::new(Vec::new()),
+        });
+        let app = Router::new().fallback(handle).with_state(state.clone());
+        let server = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(app.into_make_service());
+        let addr = server.local_addr();
+        let task = tokio::spawn(async move {
+            let _ = server.await;
+        });
+        RunningProvider { addr, state, task }
+    }
+}
+
+/// A started [`MockProvider`] and its request counts
+#[derive(Debug)]
+pub struct RunningProvider {
+    addr: SocketAddr,
+    state: Arc<ProviderState>,
+    task: JoinHandle<()>,
+}
+
+impl RunningProvider {
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Full URL of `path` on the provider
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+
+    /// Requests without proof, answered with a 402
+    pub fn challenged(&self) -> u64 {
+        self.state.challenged.load(Ordering::Relaxed)
+    }
+
+    /// Requests served after their proof checked out
+    pub fn accepted(&self) -> u64 {
+        self.state.accepted.load(Ordering::Relaxed)
+    }
+
+    /// Requests whose proof was refused, answered with a 402
+    pub fn rejected(&self) -> u64 {
+        self.state.rejected.load(Ordering::Relaxed)
+    }
+
+    /// Reasons for each rejected request, oldest first
+    pub fn rejections(&self) -> Vec<Rejection> {
+        self.state.rejections.lock().unwrap().clone()
+    }
+}
+
+impl Drop for RunningProvider {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[derive(Debug)]
+struct ProviderState {
+    /// Reads streams uncached, so every request sees the ledger as it is
+    verifier: BackendVerifier<MemoryBackend>,
+    routes: Vec<PaidRoute>,
+    challenged: AtomicU64,
+    accepted: AtomicU64,
+    rejected: AtomicU64,
+    rejections: Mutex<Vec<Rejection>>,
+}
+
+impl ProviderState {
+    fn reject(&self, route: &PaidRoute, rejection: Rejection) -> Response {
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+        self.rejections.lock().unwrap().push(rejection);
+        paywall::challenge(&route.requirement)
+    }
+}
+
+async fn handle(State(state): State<Arc<ProviderState>>, uri: Uri, headers: HeaderMap) -> Response {
+    let Some(route) = state.routes.iter().find(|route| route.path == uri.path()) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let proof = match PaymentProof::from_header_map(&headers) {
+        Ok(Some(proof)) => proof,
+        Ok(None) => {
+            state.challenged.fetch_add(1, Ordering::Relaxed);
+            return paywall::challenge(&route.requirement);
+        }
+        Err(e) => return state.reject(route, Rejection::InvalidProof(e)),
+    };
+    if proof.dialect != route.requirement.dialect {
+        let rejection = Rejection::WrongDialect {
+            expected: route.requirement.dialect,
+            got: proof.dialect,
+        };
+        return state.reject(route, rejection);
+    }
+
+    match state.verifier.verify(&route.requirement, &proof).await {
+        Ok(_) => {
+            state.accepted.fetch_add(1, Ordering::Relaxed);
+            (StatusCode::OK, route.body.clone()).into_response()
+        }
+        Err(rejection) => state.reject(route, rejection),
+    }
+}
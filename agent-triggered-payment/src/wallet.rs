@@ -0,0 +1,359 @@
+//! Signing keys for paying agents. Key material is held in buffers that are
+//! zeroized on drop and never appears in `Debug` output or errors.
+//!
+//! Fleets derive every agent from one BIP-39 mnemonic ([`HdWallet`]), one
+//! BIP-44 account per agent: agent `N` signs with `m/44'/60'/N'/0/0` and its
+//! per-service child addresses are `m/44'/60'/N'/0/k` for `k >= 1`.
+
+use ethers::core::k256::ecdsa::SigningKey;
+use ethers::core::utils::hex;
+use ethers::signers::coins_bip39::{English, Mnemonic};
+use ethers::signers::{LocalWallet, Signer};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+use zeroize::Zeroizing;
+
+use crate::address::Address;
+
+/// Env var read by [`KeySource::default`]
+pub const PRIVATE_KEY_ENV: &str = "AGENT_PRIVATE_KEY";
+
+/// Env var holding a fleet's BIP-39 mnemonic
+pub const MNEMONIC_ENV: &str = "AGENT_MNEMONIC";
+
+/// Hardened BIP-32 indices must stay below 2^31
+const MAX_ACCOUNT: u32 = (1 << 31) - 1;
+
+#[derive(Error, Debug)]
+pub enum WalletError {
+    #[error("Environment variable {0} is not set")]
+    MissingEnv(String),
+    #[error("Could not read key file {path}: {source}")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    /// The reason never includes the key itself
+    #[error("Invalid private key from {origin}: {reason}")]
+    InvalidKey { origin: String, reason: &'static str },
+    #[error("Could not decrypt keystore {path}: {reason}")]
+    Keystore { path: PathBuf, reason: String },
+    /// Details are omitted since they can quote words of the phrase
+    #[error("Invalid BIP-39 mnemonic")]
+    InvalidMnemonic,
+    #[error("Cannot derive {path}: {reason}")]
+    Derivation { path: String, reason: String },
+    #[error("Could not use service index {path}: {reason}")]
+    ServiceIndex { path: PathBuf, reason: String },
+}
+
+/// Where an agent's signing key comes from
+#[derive(Clone)]
+pub enum KeySource {
+    /// Hex private key in an environment variable
+    Env(String),
+    /// File holding a hex private key
+    KeyFile(PathBuf),
+    /// Web3 Secret Storage (V3 keystore JSON) file and its passphrase
+    Keystore {
+        path: PathBuf,
+        passphrase: Zeroizing<String>,
+    },
+    /// BIP-44 account `index` of the mnemonic in an environment variable
+    Mnemonic { env: String, index: u32 },
+}
+
+impl KeySource {
+    pub fn keystore(path: impl Into<PathBuf>, passphrase: impl Into<String>) -> Self {
+        Self::Keystore {
+            path: path.into(),
+            passphrase: Zeroizing::new(passphrase.into()),
+        }
+    }
+}
+
+impl Default for KeySource {
+    fn default() -> Self {
+        Self::Env(PRIVATE_KEY_ENV.to_string())
+    }
+}
+
+impl fmt::Debug for KeySource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Env(var) => f.debug_tuple("Env").field(var).finish(),
+            Self::KeyFile(path) => f.debug_tuple("KeyFile").field(path).finish(),
+            Self::Keystore { path, .. } => f
+                .debug_struct("Keystore")
+                .field("path", path)
+                .field("passphrase", &"<redacted>")
+                .finish(),
+            Self::Mnemonic { env, index } => f
+                .debug_struct("Mnemonic")
+                .field("env", env)
+                .field("index", index)
+                .finish(),
+        }
+    }
+}
+
+/// Local signing key for an agent. The address is always derived from the
+/// key; `Debug` shows only the address.
+#[derive(Clone)]
+pub struct AgentWallet {
+    signer: LocalWallet,
+}
+
+impl AgentWallet {
+    pub fn load(source: &KeySource) -> Result<Self, WalletError> {
+        match source {
+            KeySource::Env(var) => Self::from_env(var),
+            KeySource::KeyFile(path) => Self::from_key_file(path),
+            KeySource::Keystore { path, passphrase } => Self::from_keystore(path, passphrase),
+            KeySource::Mnemonic { env, index } => HdWallet::from_env(env)?.agent(*index),
+        }
+    }
+
+    /// Hex private key (with or without `0x`) from an environment variable
+    pub fn from_env(var: &str) -> Result<Self, WalletError> {
+        let key = Zeroizing::new(std::env::var(var).map_err(|_| WalletError::MissingEnv(var.to_string()))?);
+        Self::from_hex(&key, &format!("${}", var))
+    }
+
+    /// Hex private key from a file; surrounding whitespace is ignored
+    pub fn from_key_file(path: &Path) -> Result<Self, WalletError> {
+        let key = Zeroizing::new(std::fs::read_to_string(path).map_err(|source| WalletError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?);
+        Self::from_hex(&key, &path.display().to_string())
+    }
+
+    /// Decrypt a V3 keystore JSON file
+    pub fn from_keystore(path: &Path, passphrase: &str) -> Result<Self, WalletError> {
+        let secret = Zeroizing::new(eth_keystore::decrypt_key(path, passphrase).map_err(|e| {
+            WalletError::Keystore {
+                path: path.to_path_buf(),
+                reason: e.to_string(),
+            }
+        })?);
+        Self::from_bytes(&secret, &path.display().to_string())
+    }
+
+    /// Parse a hex private key. `origin` names where it came from, for errors.
+    pub fn from_hex(key: &str, origin: &str) -> Result<Self, WalletError> {
+        let invalid = |reason| WalletError::InvalidKey {
+            origin: origin.to_string(),
+            reason,
+        };
+        let key = key.trim();
+        let key = key.strip_prefix("0x").unwrap_or(key);
+        if key.len() != 64 {
+            return Err(invalid("expected 32 bytes of hex"));
+        }
+
+        let mut bytes = Zeroizing::new([0u8; 32]);
+        hex::decode_to_slice(key, &mut *bytes).map_err(|_| invalid("not valid hex"))?;
+        Self::from_bytes(&*bytes, origin)
+    }
+
+    fn from_bytes(bytes: &[u8], origin: &str) -> Result<Self, WalletError> {
+        let key = SigningKey::from_slice(bytes).map_err(|_| WalletError::InvalidKey {
+            origin: origin.to_string(),
+            reason: "not a valid secp256k1 scalar",
+        })?;
+        Ok(Self { signer: LocalWallet::from(key) })
+    }
+
+    pub fn address(&self) -> Address {
+        Address::from_h160(self.signer.address())
+    }
+
+    /// The underlying ethers signer, for transaction signing
+    pub fn signer(&self) -> &LocalWallet {
+        &self.signer
+    }
+}
+
+impl fmt::Debug for AgentWallet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AgentWallet")
+            .field("address", &format_args!("{}", self.address()))
+            .finish()
+    }
+}
+
+/// BIP-39 mnemonic that a fleet of agents derives its keys from. Only the
+/// phrase is kept, zeroized on drop; keys are derived on demand.
+#[derive(Clone)]
+pub struct HdWallet {
+    phrase: Zeroizing<String>,
+}
+
+impl HdWallet {
+    pub fn from_phrase(phrase: &str) -> Result<Self, WalletError> {
+        let words: Vec<&str> = phrase.split_whitespace().collect();
+        let wallet = Self {
+            phrase: Zeroizing::new(words.join(" ")),
+        };
+        wallet.mnemonic()?;
+        Ok(wallet)
+    }
+
+    pub fn from_env(var: &str) -> Result<Self, WalletError> {
+        let phrase = Zeroizing::new(std::env::var(var).map_err(|_| WalletError::MissingEnv(var.to_string()))?);
+        Self::from_phrase(&phrase)
+    }
+
+    /// Signing key of agent `index`
+    pub fn agent(&self, index: u32) -> Result<AgentWallet, WalletError> {
+        self.derive(index, 0)
+    }
+
+    /// Child address `child` (from 1) that agent `index` pays one service from
+    pub fn service_child(&self, index: u32, child: u32) -> Result<AgentWallet, WalletError> {
+        if child == 0 {
+            return Err(WalletError::Derivation {
+                path: derivation_path(index, child),
+                reason: "child 0 is the agent's own key".to_string(),
+            });
+        }
+        self.derive(index, child)
+    }
+
+    /// Key at `m/44'/60'/account'/0/address_index`
+    pub fn derive(&self, account: u32, address_index: u32) -> Result<AgentWallet, WalletError> {
+        let path = derivation_path(account, address_index);
+        if account > MAX_ACCOUNT || address_index > MAX_ACCOUNT {
+            return Err(WalletError::Derivation {
+                path,
+                reason: "index must be below 2^31".to_string(),
+            });
+        }
+
+        let key = self
+            .mnemonic()?
+            .derive_key(path.as_str(), None)
+            .map_err(|e| WalletError::Derivation {
+                path: path.clone(),
+                reason: e.to_string(),
+            })?;
+        let signing_key: &SigningKey = key.as_ref();
+        Ok(AgentWallet {
+            signer: LocalWallet::from(signing_key.clone()),
+        })
+    }
+
+    fn mnemonic(&self) -> Result<Mnemonic<English>, WalletError> {
+        Mnemonic::new_from_phrase(&self.phrase).map_err(|_| WalletError::InvalidMnemonic)
+    }
+}
+
+impl fmt::Debug for HdWallet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HdWallet").field("phrase", &"<redacted>").finish()
+    }
+}
+
+/// BIP-44 path of an agent account's address
+pub fn derivation_path(account: u32, address_index: u32) -> String {
+    format!("m/44'/60'/{}'/0/{}", account, address_index)
+}
+
+/// Service host → HD child index, as saved in a service index file. A
+/// missing file is an empty index.
+pub fn load_service_index(path: &Path) -> Result<HashMap<String, u32>, WalletError> {
+    let invalid = |reason: String| WalletError::ServiceIndex {
+        path: path.to_path_buf(),
+        reason,
+    };
+    match std::fs::read_to_string(path) {
+        Ok(json) => serde_json::from_str(&json).map_err(|e| invalid(e.to_string())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(HashMap::new()),
+        Err(e) => Err(invalid(e.to_string())),
+    }
+}
+
+/// Write the service index, sorted by host, replacing the file whole
+pub fn save_service_index(path: &Path, index: &HashMap<String, u32>) -> Result<(), WalletError> {
+    let invalid = |reason: String| WalletError::ServiceIndex {
+        path: path.to_path_buf(),
+        reason,
+    };
+    let sorted: BTreeMap<_, _> = index.iter().collect();
+    let json = serde_json::to_string_pretty(&sorted).map_err(|e| invalid(e.to_string()))?;
+    let partial = path.with_extension("tmp");
+    std::fs::write(&partial, json).map_err(|e| invalid(e.to_string()))?;
+    std::fs::rename(&partial, path).map_err(|e| invalid(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Hardhat and anvil's default mnemonic
+    const TEST_MNEMONIC: &str = "test test test test test test test test test test test junk";
+
+    /// Hardhat account #1's key in a V3 keystore, passphrase `paystream-test`.
+    /// PBKDF2 with few rounds keeps the test fast.
+    const KEYSTORE: &str = r#"{"crypto":{"cipher":"aes-128-ctr","cipherparams":{"iv":"0102030405060708090a0b0c0d0e0f10"},"ciphertext":"bbf35444062562b9021d300226a63f4bbc6887d59f1d60e24ee21dca16eba309","kdf":"pbkdf2","kdfparams":{"c":1024,"dklen":32,"prf":"hmac-sha256","salt":"7061797374726561d2d2d2d2d2d2d2d2d2d2d2d2d2d2d2d2d2d2d2d2d2d2d2d2"},"mac":"214357b478f6ea0fa007e324f7424b154c431c27382c72752ccf13a1ab314e7d"},"id":"6f2b8a4e-6d0a-4a55-9d4c-0c1d9d0f8a51","version":3}"#;
+
+    #[test]
+    fn derives_the_standard_test_accounts() {
+        let hd = HdWallet::from_phrase(TEST_MNEMONIC).unwrap();
+
+        let root: Address = "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266".parse().unwrap();
+        let second: Address = "0x70997970C51812dc3A010C7d01b50e0d17dc79C8".parse().unwrap();
+        assert_eq!(derivation_path(0, 0), "m/44'/60'/0'/0/0");
+        assert_eq!(hd.agent(0).unwrap().address(), root);
+        assert_eq!(hd.service_child(0, 1).unwrap().address(), second);
+        assert_ne!(hd.agent(1).unwrap().address(), root);
+    }
+
+    #[test]
+    fn refuses_child_zero_and_bad_phrases() {
+        let hd = HdWallet::from_phrase(TEST_MNEMONIC).unwrap();
+
+        assert!(matches!(hd.service_child(0, 0), Err(WalletError::Derivation { .. })));
+        assert!(matches!(hd.derive(1 << 31, 0), Err(WalletError::Derivation { .. })));
+        assert!(matches!(
+            HdWallet::from_phrase("test test test test test test test test test test test test"),
+            Err(WalletError::InvalidMnemonic)
+        ));
+    }
+
+    #[test]
+    fn decrypts_a_v3_keystore() {
+        let path = std::env::temp_dir().join(format!("paystream-keystore-{}.json", std::process::id()));
+        std::fs::write(&path, KEYSTORE).unwrap();
+        let account: Address = "0x70997970C51812dc3A010C7d01b50e0d17dc79C8".parse().unwrap();
+
+        let wallet = AgentWallet::load(&KeySource::keystore(&path, "paystream-test")).unwrap();
+        assert_eq!(wallet.address(), account);
+        assert!(matches!(
+            AgentWallet::from_keystore(&path, "wrong passphrase"),
+            Err(WalletError::Keystore { path: failed, .. }) if failed == path
+        ));
+        let source = format!("{:?}", KeySource::keystore(&path, "paystream-test"));
+        assert!(!source.contains("paystream-test"), "{}", source);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn service_index_round_trips_through_a_file() {
+        let path = std::env::temp_dir().join(format!("paystream-wallet-index-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        assert!(load_service_index(&path).unwrap().is_empty());
+
+        let index = HashMap::from([("api.example.com".to_string(), 1), ("127.0.0.1:8080".to_string(), 2)]);
+        save_service_index(&path, &index).unwrap();
+        assert_eq!(load_service_index(&path).unwrap(), index);
+
+        std::fs::write(&path, "not json").unwrap();
+        assert!(matches!(load_service_index(&path), Err(WalletError::ServiceIndex { .. })));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
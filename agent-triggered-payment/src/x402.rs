@@ -1,6 +1,17 @@
+use http::header::{HeaderMap, HeaderName, HeaderValue};
 use serde::{Deserialize, Serialize};
 use reqwest::Response;
 use std::collections::HashMap;
+use thiserror::Error;
+use tracing::warn;
+
+use crate::address::{Address, AddressError};
+use crate::amount::{Amount, AmountError};
+
+/// Fallbacks when a 402 challenge omits the relevant amount
+pub(crate) const DEFAULT_DEPOSIT: Amount = Amount::from_wei_u128(1_000_000_000_000_000_000); // 1 TCRO
+pub(crate) const DEFAULT_RATE: Amount = Amount::from_wei_u128(100_000_000_000_000); // 0.0001 TCRO/sec
+pub(crate) const DEFAULT_AMOUNT: Amount = Amount::from_wei_u128(1_000_000_000_000_000); // 0.001 TCRO
 
 /// x402 Payment Mode
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -9,17 +20,55 @@ pub enum PaymentMode {
     Streaming,
 }
 
+impl PaymentMode {
+    /// Mode value as sent in x402 headers and bodies
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PaymentMode::PerRequest => "per-request",
+            PaymentMode::Streaming => "streaming",
+        }
+    }
+}
+
+/// Header dialect a 402 challenge was expressed in
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum HeaderDialect {
+    /// `X-FlowPay-*` headers (original FlowPay scheme)
+    FlowPay,
+    /// `X-PayStream-*` headers emitted by `payStreamMiddleware.js`
+    PayStream,
+    /// Open x402 spec: `accepts` array in the body, `X-PAYMENT` proof header.
+    /// See [`crate::x402_spec`].
+    Spec,
+}
+
+/// Which 402 format a request expects, chosen per request
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum X402Protocol {
+    /// FlowPay/PayStream headers with the PayStream JSON body as fallback
+    #[default]
+    PayStream,
+    /// Open x402 spec
+    Spec,
+}
+
 /// Payment requirement extracted from HTTP 402 response headers
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct X402PaymentRequirement {
-    pub recipient: String,
-    pub amount: Option<String>,
+    pub recipient: Address,
+    pub amount: Option<Amount>,
     pub mode: PaymentMode,
-    pub rate_per_second: Option<String>,
-    pub min_deposit: Option<String>,
+    pub rate_per_second: Option<Amount>,
+    pub min_deposit: Option<Amount>,
     pub description: Option<String>,
     pub network: Option<String>,
     pub token: Option<String>,
+    /// PayStreamStream contract address (PayStream dialect only)
+    pub contract: Option<Address>,
+    /// Payment scheme, e.g. `exact` (Spec dialect only)
+    pub scheme: Option<String>,
+    /// Dialect the requirement was parsed from
+    pub dialect: HeaderDialect,
 }
 
 /// x402 header names
@@ -34,87 +83,458 @@ pub mod headers {
     pub const FLOWPAY_NETWORK: &str = "X-FlowPay-Network";
     pub const FLOWPAY_DESCRIPTION: &str = "X-FlowPay-Description";
     pub const FLOWPAY_STREAM: &str = "X-FlowPay-Stream";
+    pub const FLOWPAY_TX_HASH: &str = "X-Payment-TxHash";
+
+    pub const PAYSTREAM_MODE: &str = "X-PayStream-Mode";
+    pub const PAYSTREAM_RATE: &str = "X-PayStream-Rate";
+    pub const PAYSTREAM_RECIPIENT: &str = "X-PayStream-Recipient";
+    pub const PAYSTREAM_CONTRACT: &str = "X-PayStream-Contract";
+    pub const PAYSTREAM_CURRENCY: &str = "X-PayStream-Currency";
+    pub const PAYSTREAM_MIN_DEPOSIT: &str = "X-PayStream-MinDeposit";
+    pub const PAYSTREAM_DESCRIPTION: &str = "X-PayStream-Description";
+    pub const PAYSTREAM_STREAM_ID: &str = "X-PayStream-Stream-Id";
+    pub const PAYSTREAM_TX_HASH: &str = "X-PayStream-Tx-Hash";
+}
+
+/// JSON body `send402Response` returns alongside the 402 headers
+#[derive(Debug, Serialize, Deserialize)]
+struct PaymentRequiredBody {
+    #[serde(default, skip_deserializing)]
+    message: String,
+    requirements: OneOrMany<BodyRequirements>,
+}
+
+/// `requirements` is a single object, or an array when a service offers
+/// several payment options
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BodyRequirements {
+    recipient: String,
+    mode: Option<String>,
+    price: Option<String>,
+    currency: Option<String>,
+    contract: Option<String>,
+    min_deposit: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+}
+
+/// Why a 402 challenge could not be turned into a payment requirement
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum X402Error {
+    #[error("response is not an x402 challenge (no X-Payment-Required header)")]
+    NotPaymentRequired,
+    #[error("missing header: {0}")]
+    MissingHeader(&'static str),
+    #[error("header {name} is not valid: {reason}")]
+    InvalidHeader { name: String, reason: String },
+    #[error("unknown payment mode: {0}")]
+    UnknownMode(String),
+    #[error("{field} is not a valid amount: {source}")]
+    InvalidAmount {
+        field: &'static str,
+        #[source]
+        source: AmountError,
+    },
+    #[error("{field} is not a valid address: {source}")]
+    InvalidAddress {
+        field: &'static str,
+        #[source]
+        source: AddressError,
+    },
+    #[error("recipient is the zero address")]
+    ZeroRecipient,
+    #[error("streaming challenge has no rate")]
+    MissingRate,
+    #[error("per-request challenge has no amount")]
+    MissingAmount,
+    #[error("402 body is not a payment requirement: {0}")]
+    InvalidBody(String),
+    /// Each offer's reason for being refused, in the challenge's order
+    #[error("402 challenge offers no payable option{}", listed(.reasons))]
+    NoPayableOffer { reasons: Vec<X402Error> },
+    #[error("{0:?} challenges have no header form")]
+    NoHeaderForm(HeaderDialect),
+    #[error("offer is on network {network}, not {expected}")]
+    UnsupportedNetwork { network: String, expected: String },
+    #[error("offer asset {0} is not the native token")]
+    UnsupportedAsset(String),
+}
+
+/// `reasons` as a `: `-led, `; `-separated list, or nothing if empty
+fn listed(reasons: &[X402Error]) -> String {
+    if reasons.is_empty() {
+        return String::new();
+    }
+    let reasons: Vec<String> = reasons.iter().map(ToString::to_string).collect();
+    format!(": {}", reasons.join("; "))
+}
+
+/// Read a header as UTF-8, treating empty values as absent
+fn header_value(headers: &HeaderMap, name: &'static str) -> Result<Option<String>, X402Error> {
+    match headers.get(name) {
+        None => Ok(None),
+        Some(value) => value
+            .to_str()
+            .map(|v| Some(v.trim().to_string()).filter(|v| !v.is_empty()))
+            .map_err(|e| X402Error::InvalidHeader {
+                name: name.to_string(),
+                reason: e.to_string(),
+            }),
+    }
+}
+
+/// Parse an optional amount field, failing if it is present but invalid
+fn parse_amount(field: &'static str, value: Option<String>) -> Result<Option<Amount>, X402Error> {
+    value
+        .map(|v| v.parse())
+        .transpose()
+        .map_err(|source| X402Error::InvalidAmount { field, source })
+}
+
+/// Parse a payment recipient, rejecting malformed and zero addresses
+fn parse_recipient(field: &'static str, value: &str) -> Result<Address, X402Error> {
+    let recipient: Address = value
+        .parse()
+        .map_err(|source| X402Error::InvalidAddress { field, source })?;
+    if recipient.is_zero() {
+        return Err(X402Error::ZeroRecipient);
+    }
+    Ok(recipient)
+}
+
+/// Parse an optional contract address
+fn parse_contract(field: &'static str, value: Option<String>) -> Result<Option<Address>, X402Error> {
+    value
+        .map(|c| c.parse())
+        .transpose()
+        .map_err(|source| X402Error::InvalidAddress { field, source })
+}
+
+/// Parse a payment mode; an absent mode means per-request
+fn parse_mode(value: Option<&str>) -> Result<PaymentMode, X402Error> {
+    match value.map(str::to_ascii_lowercase).as_deref() {
+        Some("streaming") | Some("stream") => Ok(PaymentMode::Streaming),
+        None | Some("per-request") | Some("per_request") | Some("direct") => Ok(PaymentMode::PerRequest),
+        Some(_) => Err(X402Error::UnknownMode(value.unwrap_or_default().to_string())),
+    }
 }
 
 impl X402PaymentRequirement {
-    /// Parse x402 payment requirements from HTTP headers
-    pub fn from_headers(headers: &HashMap<String, String>) -> Option<Self> {
+    /// Parse x402 payment requirements from a raw header map.
+    /// Header names are matched case-insensitively.
+    pub fn from_headers(raw: &HashMap<String, String>) -> Result<Self, X402Error> {
+        let mut map = HeaderMap::with_capacity(raw.len());
+        for (name, value) in raw {
+            let invalid = |reason: String| X402Error::InvalidHeader { name: name.clone(), reason };
+            let header_name = HeaderName::from_bytes(name.as_bytes()).map_err(|e| invalid(e.to_string()))?;
+            let header_value = HeaderValue::from_str(value).map_err(|e| invalid(e.to_string()))?;
+            map.append(header_name, header_value);
+        }
+        Self::from_header_map(&map)
+    }
+
+    /// Parse from actual reqwest Response headers
+    pub fn from_response(response: &Response) -> Result<Self, X402Error> {
+        Self::from_header_map(response.headers())
+    }
+
+    /// Parse from an incoming hyper/axum request, e.g. in a proxy
+    pub fn from_request<B>(request: &http::Request<B>) -> Result<Self, X402Error> {
+        Self::from_header_map(request.headers())
+    }
+
+    /// Parse x402 payment requirements from HTTP headers. FlowPay headers
+    /// take precedence when a response carries both dialects.
+    pub fn from_header_map(map: &HeaderMap) -> Result<Self, X402Error> {
+        let get = |name| header_value(map, name);
+
         // Must have payment required header
-        if !headers.contains_key(headers::PAYMENT_REQUIRED) {
-            return None;
+        if !map.contains_key(headers::PAYMENT_REQUIRED) {
+            return Err(X402Error::NotPaymentRequired);
         }
 
-        let recipient = headers.get(headers::FLOWPAY_RECIPIENT)?.clone();
-        
-        let mode = match headers.get(headers::FLOWPAY_MODE).map(|s| s.as_str()) {
-            Some("streaming") | Some("stream") => PaymentMode::Streaming,
-            _ => PaymentMode::PerRequest,
+        // A challenge is FlowPay if it names a FlowPay recipient, or carries
+        // other FlowPay headers and no PayStream recipient to fall back on.
+        let flowpay_headers = [
+            headers::FLOWPAY_RECIPIENT,
+            headers::FLOWPAY_MODE,
+            headers::FLOWPAY_AMOUNT,
+            headers::FLOWPAY_RATE,
+            headers::FLOWPAY_MIN_DEPOSIT,
+            headers::FLOWPAY_TOKEN,
+            headers::FLOWPAY_NETWORK,
+        ];
+        let is_flowpay = get(headers::FLOWPAY_RECIPIENT)?.is_some()
+            || (get(headers::PAYSTREAM_RECIPIENT)?.is_none()
+                && flowpay_headers.iter().any(|name| map.contains_key(*name)));
+
+        let requirement = if is_flowpay {
+            let recipient = get(headers::FLOWPAY_RECIPIENT)?
+                .ok_or(X402Error::MissingHeader(headers::FLOWPAY_RECIPIENT))?;
+            Self {
+                recipient: parse_recipient(headers::FLOWPAY_RECIPIENT, &recipient)?,
+                amount: parse_amount(headers::FLOWPAY_AMOUNT, get(headers::FLOWPAY_AMOUNT)?)?,
+                mode: parse_mode(get(headers::FLOWPAY_MODE)?.as_deref())?,
+                rate_per_second: parse_amount(headers::FLOWPAY_RATE, get(headers::FLOWPAY_RATE)?)?,
+                min_deposit: parse_amount(headers::FLOWPAY_MIN_DEPOSIT, get(headers::FLOWPAY_MIN_DEPOSIT)?)?,
+                description: get(headers::FLOWPAY_DESCRIPTION)?,
+                network: get(headers::FLOWPAY_NETWORK)?,
+                token: get(headers::FLOWPAY_TOKEN)?,
+                contract: None,
+                scheme: None,
+                dialect: HeaderDialect::FlowPay,
+            }
+        } else {
+            let recipient = get(headers::PAYSTREAM_RECIPIENT)?
+                .ok_or(X402Error::MissingHeader(headers::PAYSTREAM_RECIPIENT))?;
+            let mode = parse_mode(get(headers::PAYSTREAM_MODE)?.as_deref())?;
+
+            // The middleware sends the route price as X-PayStream-Rate for both
+            // modes: per second when streaming, per call otherwise.
+            let price = parse_amount(headers::PAYSTREAM_RATE, get(headers::PAYSTREAM_RATE)?)?;
+            let (amount, rate_per_second) = match mode {
+                PaymentMode::Streaming => (None, price),
+                PaymentMode::PerRequest => (price, None),
+            };
+
+            Self {
+                recipient: parse_recipient(headers::PAYSTREAM_RECIPIENT, &recipient)?,
+                amount,
+                mode,
+                rate_per_second,
+                min_deposit: parse_amount(headers::PAYSTREAM_MIN_DEPOSIT, get(headers::PAYSTREAM_MIN_DEPOSIT)?)?,
+                description: get(headers::PAYSTREAM_DESCRIPTION)?,
+                network: None,
+                token: get(headers::PAYSTREAM_CURRENCY)?,
+                contract: parse_contract(headers::PAYSTREAM_CONTRACT, get(headers::PAYSTREAM_CONTRACT)?)?,
+                scheme: None,
+                dialect: HeaderDialect::PayStream,
+            }
         };
 
-        Some(Self {
-            recipient,
-            amount: headers.get(headers::FLOWPAY_AMOUNT).cloned(),
-            mode,
-            rate_per_second: headers.get(headers::FLOWPAY_RATE).cloned(),
-            min_deposit: headers.get(headers::FLOWPAY_MIN_DEPOSIT).cloned(),
-            description: headers.get(headers::FLOWPAY_DESCRIPTION).cloned(),
-            network: headers.get(headers::FLOWPAY_NETWORK).cloned(),
-            token: headers.get(headers::FLOWPAY_TOKEN).cloned(),
-        })
+        requirement.validate()
     }
 
-    /// Parse from actual reqwest Response headers
-    pub fn from_response(response: &Response) -> Option<Self> {
-        let headers = response.headers();
-        
-        // Check if payment required
-        if !headers.contains_key(headers::PAYMENT_REQUIRED) {
-            return None;
-        }
-
-        let recipient = headers.get(headers::FLOWPAY_RECIPIENT)?
-            .to_str().ok()?.to_string();
-        
-        let mode = match headers.get(headers::FLOWPAY_MODE)
-            .and_then(|v| v.to_str().ok()) 
-        {
-            Some("streaming") | Some("stream") => PaymentMode::Streaming,
-            _ => PaymentMode::PerRequest,
+    /// Parse the `requirements` object of a PayStream 402 JSON body.
+    /// Used when a CDN or proxy stripped the custom headers.
+    pub fn from_json_body(body: &str) -> Result<Self, X402Error> {
+        Self::offers_from_json_body(body)?
+            .into_iter()
+            .next()
+            .ok_or(X402Error::NoPayableOffer { reasons: Vec::new() })
+    }
+
+    /// Parse every offer in a PayStream 402 JSON body. Fails on the first
+    /// invalid entry.
+    pub fn offers_from_json_body(body: &str) -> Result<Vec<Self>, X402Error> {
+        let parsed: PaymentRequiredBody = serde_json::from_str(body)
+            .map_err(|e| X402Error::InvalidBody(e.to_string()))?;
+        match parsed.requirements {
+            OneOrMany::One(req) => Ok(vec![Self::from_body_requirements(req)?]),
+            OneOrMany::Many(reqs) => reqs.into_iter().map(Self::from_body_requirements).collect(),
+        }
+    }
+
+    /// All offers in a PayStream-protocol 402: the header offer, completed
+    /// from the matching body offer as in [`Self::reconcile`], followed by
+    /// the remaining body offers.
+    pub fn offers(headers: &HeaderMap, body: &str) -> Result<Vec<Self>, X402Error> {
+        let from_body = Self::offers_from_json_body(body);
+        let header = match Self::from_header_map(headers) {
+            Ok(header) => header,
+            Err(header_err) => {
+                return match (header_err, from_body) {
+                    (_, Ok(offers)) if !offers.is_empty() => Ok(offers),
+                    (X402Error::NotPaymentRequired, Err(body_err)) => Err(body_err),
+                    (header_err, _) => Err(header_err),
+                };
+            }
         };
 
-        Some(Self {
-            recipient,
-            amount: headers.get(headers::FLOWPAY_AMOUNT)
-                .and_then(|v| v.to_str().ok()).map(String::from),
+        let mut body_offers = from_body.unwrap_or_default();
+        let matching = body_offers
+            .iter()
+            .position(|b| b.recipient == header.recipient && b.mode == header.mode)
+            .map(|i| body_offers.remove(i));
+
+        let mut offers = vec![Self::reconcile(Ok(header), matching.ok_or(X402Error::NoPayableOffer { reasons: Vec::new() }))?];
+        offers.extend(body_offers);
+        Ok(offers)
+    }
+
+    fn from_body_requirements(req: BodyRequirements) -> Result<Self, X402Error> {
+        let non_empty = |v: Option<String>| v.filter(|v| !v.is_empty());
+
+        let mode = parse_mode(req.mode.as_deref())?;
+        let price = parse_amount("requirements.price", non_empty(req.price))?;
+        let (amount, rate_per_second) = match mode {
+            PaymentMode::Streaming => (None, price),
+            PaymentMode::PerRequest => (price, None),
+        };
+
+        Self {
+            recipient: parse_recipient("requirements.recipient", &req.recipient)?,
+            amount,
             mode,
-            rate_per_second: headers.get(headers::FLOWPAY_RATE)
-                .and_then(|v| v.to_str().ok()).map(String::from),
-            min_deposit: headers.get(headers::FLOWPAY_MIN_DEPOSIT)
-                .and_then(|v| v.to_str().ok()).map(String::from),
-            description: headers.get(headers::FLOWPAY_DESCRIPTION)
-                .and_then(|v| v.to_str().ok()).map(String::from),
-            network: headers.get(headers::FLOWPAY_NETWORK)
-                .and_then(|v| v.to_str().ok()).map(String::from),
-            token: headers.get(headers::FLOWPAY_TOKEN)
-                .and_then(|v| v.to_str().ok()).map(String::from),
-        })
+            rate_per_second,
+            min_deposit: parse_amount("requirements.minDeposit", non_empty(req.min_deposit))?,
+            description: non_empty(req.description),
+            network: None,
+            token: non_empty(req.currency),
+            contract: parse_contract("requirements.contract", non_empty(req.contract))?,
+            scheme: None,
+            dialect: HeaderDialect::PayStream,
+        }
+        .validate()
+    }
+
+    /// Check that the mode has the price it needs
+    fn validate(self) -> Result<Self, X402Error> {
+        match self.mode {
+            PaymentMode::Streaming if self.rate_per_second.is_none() => Err(X402Error::MissingRate),
+            PaymentMode::PerRequest if self.amount.is_none() => Err(X402Error::MissingAmount),
+            _ => Ok(self),
+        }
+    }
+
+    /// Combine header- and body-derived requirements.
+    ///
+    /// Headers are authoritative. The body only fills fields the headers
+    /// left empty, and only when both agree on recipient and mode; if they
+    /// disagree the body is ignored. With no usable headers the body is
+    /// used on its own. When neither parses, the header error is returned
+    /// unless the headers were missing entirely.
+    pub fn reconcile(
+        from_headers: Result<Self, X402Error>,
+        from_body: Result<Self, X402Error>,
+    ) -> Result<Self, X402Error> {
+        match (from_headers, from_body) {
+            (Ok(mut header), Ok(body)) => {
+                let consistent = header.recipient == body.recipient && header.mode == body.mode;
+                if !consistent {
+                    warn!("x402 headers and 402 body disagree; using headers");
+                    return Ok(header);
+                }
+
+                header.amount = header.amount.or(body.amount);
+                header.rate_per_second = header.rate_per_second.or(body.rate_per_second);
+                header.min_deposit = header.min_deposit.or(body.min_deposit);
+                header.token = header.token.or(body.token);
+                header.contract = header.contract.or(body.contract);
+                Ok(header)
+            }
+            (Ok(header), Err(_)) => Ok(header),
+            (Err(_), Ok(body)) => Ok(body),
+            (Err(X402Error::NotPaymentRequired), Err(body_err)) => Err(body_err),
+            (Err(header_err), Err(_)) => Err(header_err),
+        }
+    }
+
+    /// Price carried in the single PayStream price field for this mode
+    fn price(&self) -> Option<Amount> {
+        match self.mode {
+            PaymentMode::Streaming => self.rate_per_second,
+            PaymentMode::PerRequest => self.amount,
+        }
+    }
+
+    /// Serialize as 402 response headers in the given dialect.
+    ///
+    /// The round trip holds within one dialect: when `dialect` is the one
+    /// `self` was parsed from, [`Self::from_header_map`] gives back an equal
+    /// value for every field the dialect carries. FlowPay has no contract
+    /// header, and PayStream has no network header and a single price.
+    /// Serializing in another dialect parses back with that dialect, which
+    /// equality compares.
+    pub fn to_headers(&self, dialect: HeaderDialect) -> Result<HeaderMap, X402Error> {
+        let mut fields: Vec<(&'static str, String)> = vec![(headers::PAYMENT_REQUIRED, "true".to_string())];
+        let mut push = |name, value: Option<String>| {
+            if let Some(value) = value {
+                fields.push((name, value));
+            }
+        };
+
+        match dialect {
+            HeaderDialect::FlowPay => {
+                push(headers::FLOWPAY_MODE, Some(self.mode.as_str().to_string()));
+                push(headers::FLOWPAY_RECIPIENT, Some(self.recipient.to_string()));
+                push(headers::FLOWPAY_AMOUNT, self.amount.map(|a| a.to_string()));
+                push(headers::FLOWPAY_RATE, self.rate_per_second.map(|r| r.to_string()));
+                push(headers::FLOWPAY_MIN_DEPOSIT, self.min_deposit.map(|d| d.to_string()));
+                push(headers::FLOWPAY_DESCRIPTION, self.description.clone());
+                push(headers::FLOWPAY_NETWORK, self.network.clone());
+                push(headers::FLOWPAY_TOKEN, self.token.clone());
+            }
+            HeaderDialect::PayStream => {
+                push(headers::PAYSTREAM_MODE, Some(self.mode.as_str().to_string()));
+                push(headers::PAYSTREAM_RECIPIENT, Some(self.recipient.to_string()));
+                push(headers::PAYSTREAM_RATE, self.price().map(|p| p.to_string()));
+                push(headers::PAYSTREAM_MIN_DEPOSIT, self.min_deposit.map(|d| d.to_string()));
+                push(headers::PAYSTREAM_CONTRACT, self.contract.map(|c| c.to_string()));
+                push(headers::PAYSTREAM_CURRENCY, self.token.clone());
+                push(headers::PAYSTREAM_DESCRIPTION, self.description.clone());
+            }
+            HeaderDialect::Spec => return Err(X402Error::NoHeaderForm(dialect)),
+        }
+
+        let mut map = HeaderMap::with_capacity(fields.len());
+        for (name, value) in fields {
+            let header_value = HeaderValue::from_str(&value).map_err(|e| X402Error::InvalidHeader {
+                name: name.to_string(),
+                reason: e.to_string(),
+            })?;
+            let header_name = HeaderName::from_bytes(name.as_bytes()).expect("x402 header names are valid");
+            map.insert(header_name, header_value);
+        }
+        Ok(map)
+    }
+
+    /// Serialize as the JSON body `payStreamMiddleware.js` sends with a 402.
+    /// [`Self::from_json_body`] reads it back as a PayStream requirement; the
+    /// body has no network field, so only PayStream values round-trip equal.
+    pub fn to_json_body(&self) -> String {
+        let body = PaymentRequiredBody {
+            message: "Payment Required".to_string(),
+            requirements: OneOrMany::One(BodyRequirements {
+                recipient: self.recipient.to_string(),
+                mode: Some(self.mode.as_str().to_string()),
+                price: self.price().map(|p| p.to_string()),
+                currency: self.token.clone(),
+                contract: self.contract.map(|c| c.to_string()),
+                min_deposit: self.min_deposit.map(|d| d.to_string()),
+                description: self.description.clone(),
+            }),
+        };
+        serde_json::to_string(&body).expect("402 body serializes")
     }
 
     /// Display payment requirement in a user-friendly format
     pub fn display(&self) -> String {
+        let symbol = self.token.as_deref().unwrap_or("TCRO");
         let mut lines = vec![
             format!("├─ Recipient: {}", self.recipient),
             format!("├─ Mode: {:?}", self.mode),
         ];
 
-        if let Some(ref rate) = self.rate_per_second {
-            lines.push(format!("├─ Rate: {} TCRO/second", rate));
+        if let Some(rate) = self.rate_per_second {
+            lines.push(format!("├─ Rate: {}/second", rate.with_symbol(symbol)));
+        }
+        if let Some(deposit) = self.min_deposit {
+            lines.push(format!("├─ Min Deposit: {}", deposit.with_symbol(symbol)));
         }
-        if let Some(ref deposit) = self.min_deposit {
-            lines.push(format!("├─ Min Deposit: {} TCRO", deposit));
+        if let Some(amount) = self.amount {
+            lines.push(format!("├─ Amount: {}", amount.with_symbol(symbol)));
         }
-        if let Some(ref amount) = self.amount {
-            lines.push(format!("├─ Amount: {} TCRO", amount));
+        if let Some(contract) = self.contract {
+            lines.push(format!("├─ Contract: {}", contract));
         }
         if let Some(ref desc) = self.description {
             lines.push(format!("└─ Description: {}", desc));
@@ -129,26 +549,182 @@ impl X402PaymentRequirement {
 pub struct PaymentProof {
     pub stream_id: Option<u64>,
     pub tx_hash: Option<String>,
-    pub amount_paid: String,
+    pub amount_paid: Amount,
     pub mode: PaymentMode,
+    /// Dialect of the proof headers, matching the 402 challenge
+    pub dialect: HeaderDialect,
+    /// Block the payment was mined in, when settled on chain
+    #[serde(default)]
+    pub block_number: Option<u64>,
+    /// Gas paid on top of `amount_paid`, when settled on chain
+    #[serde(default)]
+    pub gas_cost: Option<Amount>,
 }
 
 impl PaymentProof {
-    pub fn streaming(stream_id: u64, deposit: &str) -> Self {
+    pub fn streaming(stream_id: u64, deposit: Amount, dialect: HeaderDialect) -> Self {
         Self {
             stream_id: Some(stream_id),
             tx_hash: None,
-            amount_paid: deposit.to_string(),
+            amount_paid: deposit,
             mode: PaymentMode::Streaming,
+            dialect,
+            block_number: None,
+            gas_cost: None,
         }
     }
 
-    pub fn per_request(tx_hash: &str, amount: &str) -> Self {
+    pub fn per_request(tx_hash: &str, amount: Amount, dialect: HeaderDialect) -> Self {
         Self {
             stream_id: None,
             tx_hash: Some(tx_hash.to_string()),
-            amount_paid: amount.to_string(),
+            amount_paid: amount,
             mode: PaymentMode::PerRequest,
+            dialect,
+            block_number: None,
+            gas_cost: None,
+        }
+    }
+
+    /// Record where and at what gas cost the payment settled
+    pub fn with_receipt(mut self, block_number: Option<u64>, gas_cost: Amount) -> Self {
+        self.block_number = block_number;
+        self.gas_cost = Some(gas_cost);
+        self
+    }
+
+    /// Read the proof a client attached to a request, as a service would.
+    /// FlowPay headers take precedence, as in
+    /// [`X402PaymentRequirement::from_header_map`]. Returns `None` when the
+    /// request carries no proof. The amount is unknown until the service
+    /// looks the payment up, so `amount_paid` is zero.
+    pub fn from_header_map(map: &HeaderMap) -> Result<Option<Self>, X402Error> {
+        let dialects = [
+            (HeaderDialect::FlowPay, headers::FLOWPAY_STREAM, headers::FLOWPAY_TX_HASH),
+            (HeaderDialect::PayStream, headers::PAYSTREAM_STREAM_ID, headers::PAYSTREAM_TX_HASH),
+        ];
+        for (dialect, stream_header, tx_header) in dialects {
+            if let Some(stream_id) = header_value(map, stream_header)? {
+                let stream_id = stream_id.parse().map_err(|e: std::num::ParseIntError| X402Error::InvalidHeader {
+                    name: stream_header.to_string(),
+                    reason: e.to_string(),
+                })?;
+                return Ok(Some(Self::streaming(stream_id, Amount::ZERO, dialect)));
+            }
+            if let Some(tx_hash) = header_value(map, tx_header)? {
+                return Ok(Some(Self::per_request(&tx_hash, Amount::ZERO, dialect)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Proof headers to attach to the retried request. Spec proofs need the
+    /// requirement to encode, see [`crate::x402_spec::PaymentPayload::for_proof`].
+    pub fn headers(&self) -> Vec<(&'static str, String)> {
+        let (stream_header, tx_header) = match self.dialect {
+            HeaderDialect::FlowPay => (headers::FLOWPAY_STREAM, headers::FLOWPAY_TX_HASH),
+            HeaderDialect::PayStream => (headers::PAYSTREAM_STREAM_ID, headers::PAYSTREAM_TX_HASH),
+            HeaderDialect::Spec => return Vec::new(),
+        };
+
+        match self.mode {
+            PaymentMode::Streaming => self.stream_id
+                .map(|id| vec![(stream_header, id.to_string())])
+                .unwrap_or_default(),
+            PaymentMode::PerRequest => self.tx_hash.clone()
+                .map(|hash| vec![(tx_header, hash)])
+                .unwrap_or_default(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RECIPIENT: &str = "0x70997970C51812dc3A010C7d01b50e0d17dc79C8";
+    const CONTRACT: &str = "0x5FbDB2315678afecb367f032d93F642f64180aa3";
+
+    fn requirement(mode: PaymentMode, dialect: HeaderDialect) -> X402PaymentRequirement {
+        let price: Amount = "0.01".parse().unwrap();
+        let (amount, rate_per_second) = match mode {
+            PaymentMode::Streaming => (None, Some(price)),
+            PaymentMode::PerRequest => (Some(price), None),
+        };
+        X402PaymentRequirement {
+            recipient: RECIPIENT.parse().unwrap(),
+            amount,
+            mode,
+            rate_per_second,
+            min_deposit: Some("1".parse().unwrap()),
+            description: Some("Market data".to_string()),
+            network: (dialect == HeaderDialect::FlowPay).then(|| "cronos-testnet".to_string()),
+            token: Some("TCRO".to_string()),
+            contract: (dialect == HeaderDialect::PayStream).then(|| CONTRACT.parse().unwrap()),
+            scheme: None,
+            dialect,
+        }
+    }
+
+    #[test]
+    fn headers_round_trip_within_a_dialect() {
+        for dialect in [HeaderDialect::FlowPay, HeaderDialect::PayStream] {
+            for mode in [PaymentMode::PerRequest, PaymentMode::Streaming] {
+                let original = requirement(mode.clone(), dialect);
+                let parsed = X402PaymentRequirement::from_header_map(&original.to_headers(dialect).unwrap()).unwrap();
+                assert_eq!(parsed, original, "{dialect:?} {mode:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn headers_in_another_dialect_parse_as_that_dialect() {
+        let original = requirement(PaymentMode::PerRequest, HeaderDialect::FlowPay);
+        let parsed = X402PaymentRequirement::from_header_map(&original.to_headers(HeaderDialect::PayStream).unwrap())
+            .unwrap();
+        assert_eq!(parsed.dialect, HeaderDialect::PayStream);
+        assert_eq!(parsed.recipient, original.recipient);
+        assert_eq!(parsed.amount, original.amount);
+        assert_eq!(parsed.network, None);
+        assert_ne!(parsed, original);
+    }
+
+    #[test]
+    fn spec_has_no_header_form() {
+        let original = requirement(PaymentMode::PerRequest, HeaderDialect::PayStream);
+        assert_eq!(
+            original.to_headers(HeaderDialect::Spec),
+            Err(X402Error::NoHeaderForm(HeaderDialect::Spec))
+        );
+    }
+
+    #[test]
+    fn json_body_round_trips() {
+        for mode in [PaymentMode::PerRequest, PaymentMode::Streaming] {
+            let original = requirement(mode.clone(), HeaderDialect::PayStream);
+            let parsed = X402PaymentRequirement::from_json_body(&original.to_json_body()).unwrap();
+            assert_eq!(parsed, original, "{mode:?}");
+        }
+    }
+
+    #[test]
+    fn missing_recipient_names_the_parsed_dialect() {
+        let mut flowpay = requirement(PaymentMode::PerRequest, HeaderDialect::FlowPay)
+            .to_headers(HeaderDialect::FlowPay)
+            .unwrap();
+        flowpay.remove(headers::FLOWPAY_RECIPIENT);
+        assert_eq!(
+            X402PaymentRequirement::from_header_map(&flowpay),
+            Err(X402Error::MissingHeader(headers::FLOWPAY_RECIPIENT))
+        );
+
+        let mut paystream = requirement(PaymentMode::PerRequest, HeaderDialect::PayStream)
+            .to_headers(HeaderDialect::PayStream)
+            .unwrap();
+        paystream.remove(headers::PAYSTREAM_RECIPIENT);
+        assert_eq!(
+            X402PaymentRequirement::from_header_map(&paystream),
+            Err(X402Error::MissingHeader(headers::PAYSTREAM_RECIPIENT))
+        );
+    }
+}
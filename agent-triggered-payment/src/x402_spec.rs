@@ -0,0 +1,326 @@
+//! Open x402 spec format: a JSON 402 body with an `accepts` array, a base64
+//! `X-PAYMENT` request header and a base64 `X-PAYMENT-RESPONSE` settlement
+//! header.
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use ethers::core::types::U256;
+use http::HeaderMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::address::Address;
+use crate::amount::{Amount, SYMBOL};
+use crate::x402::{HeaderDialect, PaymentMode, PaymentProof, X402Error, X402PaymentRequirement};
+
+/// Protocol version this module speaks
+pub const X402_VERSION: u32 = 1;
+
+/// x402 network name of Cronos testnet, where `PayStreamStream` is deployed
+pub const CRONOS_TESTNET: &str = "cronos-testnet";
+
+/// EIP-7528 placeholder address for a chain's native token
+pub const NATIVE_ASSET: &str = "0xEeeeeEeeeEeEeeEeEeEeeEEEeeeeEeeeeeeeEEeE";
+
+/// Header names used by the open x402 spec
+pub mod headers {
+    pub const X_PAYMENT: &str = "X-PAYMENT";
+    pub const X_PAYMENT_RESPONSE: &str = "X-PAYMENT-RESPONSE";
+}
+
+/// `maxAmountRequired` and similar fields are decimal strings of atomic units
+mod decimal_u256 {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(value: &U256, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(value)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<U256, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        U256::from_dec_str(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+/// One entry of the `accepts` array
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PaymentRequirements {
+    pub scheme: String,
+    pub network: String,
+    /// Maximum price in the asset's atomic units
+    #[serde(with = "decimal_u256")]
+    pub max_amount_required: U256,
+    pub resource: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub mime_type: String,
+    pub pay_to: String,
+    #[serde(default)]
+    pub max_timeout_seconds: u64,
+    pub asset: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_schema: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extra: Option<serde_json::Value>,
+}
+
+impl PaymentRequirements {
+    /// Convert to the crate's requirement type as a per-request payment.
+    /// The agent pays with plain native transfers, so only offers on
+    /// `network` whose asset is its native token (the zero address or
+    /// [`NATIVE_ASSET`]) convert; ERC-20 assets, which the `exact` scheme
+    /// pays by EIP-3009 authorization, are refused. The token is always
+    /// [`SYMBOL`], whatever `extra.name` says, so spec offers compare with
+    /// PayStream ones in negotiation.
+    pub fn to_requirement(&self, network: &str) -> Result<X402PaymentRequirement, X402Error> {
+        if self.network != network {
+            return Err(X402Error::UnsupportedNetwork {
+                network: self.network.clone(),
+                expected: network.to_string(),
+            });
+        }
+        let native = self
+            .asset
+            .parse::<Address>()
+            .is_ok_and(|asset| asset.is_zero() || asset == NATIVE_ASSET.parse().expect("valid address"));
+        if !native {
+            return Err(X402Error::UnsupportedAsset(self.asset.clone()));
+        }
+
+        let recipient: Address = self.pay_to.parse().map_err(|source| X402Error::InvalidAddress {
+            field: "payTo",
+            source,
+        })?;
+        if recipient.is_zero() {
+            return Err(X402Error::ZeroRecipient);
+        }
+
+        Ok(X402PaymentRequirement {
+            recipient,
+            amount: Some(Amount::from_wei(self.max_amount_required)),
+            mode: PaymentMode::PerRequest,
+            rate_per_second: None,
+            min_deposit: None,
+            description: Some(self.description.clone()).filter(|d| !d.is_empty()),
+            network: Some(self.network.clone()),
+            token: Some(SYMBOL.to_string()),
+            contract: None,
+            scheme: Some(self.scheme.clone()),
+            dialect: HeaderDialect::Spec,
+        })
+    }
+}
+
+/// JSON body of a spec 402 response
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PaymentRequiredResponse {
+    pub x402_version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub accepts: Vec<PaymentRequirements>,
+}
+
+impl PaymentRequiredResponse {
+    pub fn from_json_body(body: &str) -> Result<Self, X402Error> {
+        serde_json::from_str(body).map_err(|e| X402Error::InvalidBody(e.to_string()))
+    }
+
+    /// Every offer in `accepts` that converts to a payable requirement on
+    /// `network`. If none does, fails with each offer's reason.
+    pub fn requirements(&self, network: &str) -> Result<Vec<X402PaymentRequirement>, X402Error> {
+        let mut payable = Vec::new();
+        let mut reasons = Vec::new();
+        for offer in &self.accepts {
+            match offer.to_requirement(network) {
+                Ok(requirement) => payable.push(requirement),
+                Err(e) => reasons.push(e),
+            }
+        }
+        if payable.is_empty() {
+            return Err(X402Error::NoPayableOffer { reasons });
+        }
+        Ok(payable)
+    }
+}
+
+/// Payment sent to the server in the `X-PAYMENT` header
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PaymentPayload {
+    pub x402_version: u32,
+    pub scheme: String,
+    pub network: String,
+    /// Scheme-specific payload
+    pub payload: serde_json::Value,
+}
+
+impl PaymentPayload {
+    /// Payload for a native transfer already settled on chain, identified
+    /// by its transaction hash. This is not the `exact` scheme's EIP-3009
+    /// authorization, which is why [`PaymentRequirements::to_requirement`]
+    /// only accepts native-token offers.
+    pub fn for_proof(requirement: &X402PaymentRequirement, proof: &PaymentProof) -> Self {
+        Self {
+            x402_version: X402_VERSION,
+            scheme: requirement.scheme.clone().unwrap_or_else(|| "exact".to_string()),
+            network: requirement.network.clone().unwrap_or_default(),
+            payload: serde_json::json!({
+                "transaction": proof.tx_hash,
+                "amount": proof.amount_paid.wei().to_string(),
+            }),
+        }
+    }
+
+    /// Base64-encoded JSON, as sent in `X-PAYMENT`
+    pub fn encode(&self) -> String {
+        BASE64.encode(serde_json::to_vec(self).expect("payment payload serializes"))
+    }
+
+    pub fn decode(header: &str) -> Result<Self, X402Error> {
+        decode_base64_json(headers::X_PAYMENT, header)
+    }
+}
+
+/// Settlement result returned in `X-PAYMENT-RESPONSE`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SettlementResponse {
+    pub success: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_reason: Option<String>,
+    #[serde(default)]
+    pub transaction: String,
+    #[serde(default)]
+    pub network: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payer: Option<String>,
+}
+
+impl SettlementResponse {
+    pub fn decode(header: &str) -> Result<Self, X402Error> {
+        decode_base64_json(headers::X_PAYMENT_RESPONSE, header)
+    }
+
+    /// Decode the `X-PAYMENT-RESPONSE` header, if the response has one
+    pub fn from_header_map(map: &HeaderMap) -> Option<Result<Self, X402Error>> {
+        let value = map.get(headers::X_PAYMENT_RESPONSE)?;
+        Some(
+            value
+                .to_str()
+                .map_err(|e| X402Error::InvalidHeader {
+                    name: headers::X_PAYMENT_RESPONSE.to_string(),
+                    reason: e.to_string(),
+                })
+                .and_then(Self::decode),
+        )
+    }
+
+    pub fn encode(&self) -> String {
+        BASE64.encode(serde_json::to_vec(self).expect("settlement response serializes"))
+    }
+}
+
+fn decode_base64_json<T: for<'de> Deserialize<'de>>(name: &str, value: &str) -> Result<T, X402Error> {
+    let invalid = |reason: String| X402Error::InvalidHeader {
+        name: name.to_string(),
+        reason,
+    };
+    let bytes = BASE64.decode(value.trim()).map_err(|e| invalid(e.to_string()))?;
+    serde_json::from_slice(&bytes).map_err(|e| invalid(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAY_TO: &str = "0x70997970C51812dc3A010C7d01b50e0d17dc79C8";
+    const USDC: &str = "0xc01efAaF7C5C61bEbFAeb358E1161b537b8bC0e0";
+
+    fn offer(network: &str, asset: &str) -> PaymentRequirements {
+        PaymentRequirements {
+            scheme: "exact".to_string(),
+            network: network.to_string(),
+            max_amount_required: U256::from(1_000_000_000_000_000u64),
+            resource: "https://api.example.com/quote".to_string(),
+            description: String::new(),
+            mime_type: String::new(),
+            pay_to: PAY_TO.to_string(),
+            max_timeout_seconds: 60,
+            asset: asset.to_string(),
+            output_schema: None,
+            extra: Some(serde_json::json!({ "name": "CRO" })),
+        }
+    }
+
+    #[test]
+    fn converts_native_offers_on_the_configured_network() {
+        for asset in [NATIVE_ASSET, "0x0000000000000000000000000000000000000000"] {
+            let requirement = offer(CRONOS_TESTNET, asset).to_requirement(CRONOS_TESTNET).unwrap();
+
+            assert_eq!(requirement.recipient, PAY_TO.parse().unwrap());
+            assert_eq!(requirement.amount, Some("0.001".parse().unwrap()));
+            assert_eq!(requirement.mode, PaymentMode::PerRequest);
+            assert_eq!(requirement.token.as_deref(), Some("TCRO"));
+            assert_eq!(requirement.dialect, HeaderDialect::Spec);
+        }
+    }
+
+    #[test]
+    fn refuses_offers_on_another_network() {
+        assert!(matches!(
+            offer("base-sepolia", NATIVE_ASSET).to_requirement(CRONOS_TESTNET),
+            Err(X402Error::UnsupportedNetwork { network, expected })
+                if network == "base-sepolia" && expected == CRONOS_TESTNET
+        ));
+    }
+
+    #[test]
+    fn refuses_token_assets() {
+        assert!(matches!(
+            offer(CRONOS_TESTNET, USDC).to_requirement(CRONOS_TESTNET),
+            Err(X402Error::UnsupportedAsset(asset)) if asset == USDC
+        ));
+    }
+
+    #[test]
+    fn keeps_only_payable_offers() {
+        let challenge = PaymentRequiredResponse {
+            x402_version: X402_VERSION,
+            error: None,
+            accepts: vec![
+                offer(CRONOS_TESTNET, USDC),
+                offer("base-sepolia", NATIVE_ASSET),
+                offer(CRONOS_TESTNET, NATIVE_ASSET),
+            ],
+        };
+
+        let requirements = challenge.requirements(CRONOS_TESTNET).unwrap();
+        assert_eq!(requirements.len(), 1);
+        assert_eq!(requirements[0].network.as_deref(), Some(CRONOS_TESTNET));
+
+        // With none payable, every offer says why
+        let refused = challenge.requirements("cronos").unwrap_err();
+        assert_eq!(
+            refused,
+            X402Error::NoPayableOffer {
+                reasons: vec![
+                    X402Error::UnsupportedNetwork {
+                        network: CRONOS_TESTNET.to_string(),
+                        expected: "cronos".to_string(),
+                    },
+                    X402Error::UnsupportedNetwork {
+                        network: "base-sepolia".to_string(),
+                        expected: "cronos".to_string(),
+                    },
+                    X402Error::UnsupportedNetwork {
+                        network: CRONOS_TESTNET.to_string(),
+                        expected: "cronos".to_string(),
+                    },
+                ],
+            }
+        );
+        assert!(refused.to_string().starts_with("402 challenge offers no payable option: offer is on network"));
+    }
+}
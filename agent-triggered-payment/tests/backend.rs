@@ -0,0 +1,378 @@
+//! The in-memory backend's contract accounting, and the full fetch → pay →
+//! retry flow settled on it, or on a private ledger in a dry run.
+
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+
+use async_trait::async_trait;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+
+use paystream_cro::address::Address;
+use paystream_cro::amount::Amount;
+use paystream_cro::backend::{LedgerTransfer, MemoryBackend, PaymentBackend, StreamState};
+use paystream_cro::chain::{CancelledStream, ChainError, CreatedStream, Transfer};
+use paystream_cro::payment_agent::{AgentConfig, PaymentError};
+use paystream_cro::test_support::{
+    self, agent, agent_config, agent_with, funded_backend, recipient, tcro, RECIPIENT, SENDER,
+};
+use paystream_cro::wallet::{AgentWallet, HdWallet};
+
+fn ledger() -> MemoryBackend {
+    let backend = funded_backend();
+    backend.set_time(1_700_000_000);
+    backend
+}
+
+#[tokio::test]
+async fn cancel_splits_deposit_like_the_contract() {
+    let backend = ledger();
+    let recipient: Address = recipient();
+
+    let created = backend.open_stream(recipient, 3600, tcro("0.36"), "{}").await.unwrap();
+    assert_eq!(created.stream_id, 1);
+    assert_eq!(backend.balance(backend.sender()).await.unwrap(), tcro("9.64"));
+
+    backend.advance(600);
+    let state = backend.stream(created.stream_id).await.unwrap();
+    assert_eq!(state.flow_rate, tcro("0.0001"));
+    assert_eq!(state.claimable, tcro("0.06"));
+
+    let cancelled = backend.cancel_stream(created.stream_id).await.unwrap();
+    assert_eq!(cancelled.payout, tcro("0.06"));
+    assert_eq!(cancelled.refund, tcro("0.3"));
+    assert_eq!(backend.balance(backend.sender()).await.unwrap(), tcro("9.94"));
+    assert_eq!(backend.balance(recipient).await.unwrap(), tcro("0.06"));
+    assert!(!backend.stream(created.stream_id).await.unwrap().is_active);
+}
+
+#[tokio::test]
+async fn rejects_what_the_contract_would_revert_on() {
+    let backend = ledger();
+    let recipient: Address = recipient();
+
+    let created = backend.open_stream(recipient, 3600, tcro("0.36"), "{}").await.unwrap();
+    backend.cancel_stream(created.stream_id).await.unwrap();
+
+    assert!(matches!(
+        backend.cancel_stream(created.stream_id).await,
+        Err(ChainError::UnknownStream(1))
+    ));
+    assert!(matches!(backend.stream(42).await, Err(ChainError::UnknownStream(42))));
+    assert!(matches!(
+        backend.open_stream(recipient, 3600, tcro("100"), "{}").await,
+        Err(ChainError::Send(_))
+    ));
+    assert!(matches!(
+        backend.open_stream(recipient, 3600, Amount::from_wei(1000u64.into()), "{}").await,
+        Err(ChainError::InvalidStream(_))
+    ));
+}
+
+async fn paid_weather(headers: HeaderMap) -> Response {
+    if headers.contains_key("x-paystream-stream-id") {
+        return (StatusCode::OK, "paid").into_response();
+    }
+    (
+        StatusCode::PAYMENT_REQUIRED,
+        [
+            ("X-Payment-Required", "true"),
+            ("X-PayStream-Mode", "streaming"),
+            ("X-PayStream-Rate", "0.0001"),
+            ("X-PayStream-Recipient", RECIPIENT),
+            ("X-PayStream-MinDeposit", "0.36"),
+        ],
+    )
+        .into_response()
+}
+
+async fn paid_quote(headers: HeaderMap) -> Response {
+    if headers.contains_key("x-paystream-tx-hash") {
+        return (StatusCode::OK, "paid").into_response();
+    }
+    (
+        StatusCode::PAYMENT_REQUIRED,
+        [
+            ("X-Payment-Required", "true"),
+            ("X-PayStream-Mode", "per-request"),
+            ("X-PayStream-Rate", "0.001"),
+            ("X-PayStream-Recipient", RECIPIENT),
+        ],
+    )
+        .into_response()
+}
+
+/// Takes the payment and still answers 402
+async fn refusing_quote() -> Response {
+    paid_quote(HeaderMap::new()).await
+}
+
+async fn spawn_server() -> SocketAddr {
+    let app = Router::new()
+        .route("/api/weather", get(paid_weather))
+        .route("/api/quote", get(paid_quote))
+        .route("/api/refusing", get(refusing_quote));
+    test_support::spawn_server(app).await
+}
+
+#[tokio::test]
+async fn agent_settles_on_the_backend() {
+    let backend = MemoryBackend::new(SENDER.parse().unwrap());
+    backend.credit(backend.sender(), tcro("1"));
+    let agent = agent(backend.clone());
+    let addr = spawn_server().await;
+
+    let streamed = agent.fetch(&format!("http://{}/api/weather", addr)).await.unwrap();
+    let quoted = agent.fetch(&format!("http://{}/api/quote", addr)).await.unwrap();
+
+    assert_eq!(streamed.status, 200);
+    assert_eq!(quoted.status, 200);
+    let stream = backend.stream(streamed.stream_id.unwrap()).await.unwrap();
+    assert_eq!(stream.sender, backend.sender());
+    assert_eq!(stream.total_amount, tcro("0.36"));
+    assert_eq!(backend.balance(recipient()).await.unwrap(), tcro("0.001"));
+    assert_eq!(backend.balance(backend.sender()).await.unwrap(), tcro("0.639"));
+}
+
+#[tokio::test]
+async fn agent_cancel_refunds_on_the_backend() {
+    let backend = ledger();
+    let agent = agent(backend.clone());
+    let addr = spawn_server().await;
+
+    let result = agent.fetch(&format!("http://{}/api/weather", addr)).await.unwrap();
+    backend.advance(60);
+    let cancelled = agent.cancel_stream(result.stream_id.unwrap()).await.unwrap();
+
+    assert_eq!(cancelled.payout, tcro("0.006"));
+    assert_eq!(agent.budget().spent(), tcro("0.006"));
+    assert_eq!(backend.balance(backend.sender()).await.unwrap(), tcro("9.994"));
+    assert!(agent.active_streams().is_empty());
+}
+
+#[tokio::test]
+async fn rejected_payments_are_still_counted() {
+    let backend = funded_backend();
+    let agent = agent(backend.clone());
+    let addr = spawn_server().await;
+
+    let refused = agent.fetch(&format!("http://{}/api/refusing", addr)).await.unwrap_err();
+    agent.fetch(&format!("http://{}/api/quote", addr)).await.unwrap();
+
+    assert!(matches!(refused, PaymentError::Rejected { status: 402, .. }));
+    assert_eq!(refused.proof().unwrap().amount_paid, tcro("0.001"));
+    assert_eq!(agent.stats.payments_made.load(Ordering::Relaxed), 2);
+    assert_eq!(agent.total_spent(), tcro("0.002"));
+    assert_eq!(backend.balance(recipient()).await.unwrap(), tcro("0.002"));
+}
+
+#[tokio::test]
+async fn budget_holds_and_charges_gas() {
+    let gwei = Amount::from_wei(1_000_000_000u64.into());
+    let backend = funded_backend().with_gas_price(gwei);
+    let addr = spawn_server().await;
+    let weather = format!("http://{}/api/weather", addr);
+
+    // The deposit fits, but not with createStream's gas at twice the price,
+    // so the offer is turned down before anything is reserved
+    let config = AgentConfig {
+        daily_budget: tcro("0.3605"),
+        ..agent_config("gas-test", backend.sender())
+    };
+    let tight = agent_with(config, backend.clone());
+    let refused = tight.fetch(&weather).await.unwrap_err();
+    assert!(matches!(
+        refused,
+        PaymentError::NoAcceptableOffer { rationale, .. }
+            if rationale.contains("needs 0.36 up front and up to 0.001 gas, 0.3605 headroom")
+    ));
+    assert_eq!(backend.balance(backend.sender()).await.unwrap(), tcro("10"));
+
+    let agent = agent(backend.clone());
+    agent.fetch(&weather).await.unwrap();
+    agent.fetch(&format!("http://{}/api/quote", addr)).await.unwrap();
+
+    // 0.36 + 500k gas for the stream, 0.001 + 21k gas for the transfer
+    assert_eq!(agent.budget().spent(), tcro("0.361521"));
+    assert_eq!(agent.budget().reserved(), Amount::ZERO);
+    assert_eq!(agent.total_spent(), tcro("0.361521"));
+    assert_eq!(backend.balance(backend.sender()).await.unwrap(), tcro("9.638479"));
+}
+
+#[tokio::test]
+async fn service_children_survive_a_restart() {
+    let path = std::env::temp_dir().join(format!("paystream-service-index-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let hd = HdWallet::from_phrase("test test test test test test test test test test test junk").unwrap();
+    let backend = funded_backend();
+    let config = AgentConfig {
+        fresh_address_per_service: true,
+        service_index_file: Some(path.clone()),
+        ..agent_config("fleet-test", backend.sender())
+    };
+    let restarted = || agent_with(config.clone(), backend.clone()).with_hd_wallet(hd.clone()).unwrap();
+    let (first, second) = (spawn_server().await, spawn_server().await);
+
+    let agent = restarted();
+    agent.fetch(&format!("http://{}/api/quote", first)).await.unwrap();
+    let before = agent.service_addresses();
+    drop(agent);
+
+    // The host keeps its child, and the next host gets a fresh one
+    let agent = restarted();
+    assert_eq!(agent.service_addresses(), before);
+    agent.fetch(&format!("http://{}/api/quote", second)).await.unwrap();
+    let after = agent.service_addresses();
+    std::fs::remove_file(&path).unwrap();
+
+    // Child 1 of account 0 is the standard mnemonic's second account
+    assert_eq!(before.len(), 1);
+    assert_eq!(before[0].1, RECIPIENT.parse().unwrap());
+    assert_eq!(after.len(), 2);
+    assert!(after.contains(&before[0]));
+    assert!(after.iter().all(|(_, address)| *address != backend.sender()));
+}
+
+#[tokio::test]
+async fn funding_gas_is_charged_when_the_payment_fails() {
+    let gwei = Amount::from_wei(1_000_000_000u64.into());
+    let backend = funded_backend().with_gas_price(gwei);
+    let hd = HdWallet::from_phrase("test test test test test test test test test test test junk").unwrap();
+    let config = AgentConfig {
+        fresh_address_per_service: true,
+        ..agent_config("funding-test", backend.sender())
+    };
+    let root = Reverting {
+        backend: backend.clone(),
+        root: backend.sender(),
+    };
+    let agent = agent_with(config, root).with_hd_wallet(hd).unwrap();
+    let addr = spawn_server().await;
+
+    let failed = agent.fetch(&format!("http://{}/api/weather", addr)).await.unwrap_err();
+    assert!(matches!(failed, PaymentError::PaymentFailed { .. }));
+
+    // The child holds what it was sent; only the funding transfer's 21k gas is gone
+    let child = agent.service_addresses()[0].1;
+    let funded = backend.balance(child).await.unwrap();
+    let sender = backend.balance(backend.sender()).await.unwrap();
+    assert_eq!(sender.saturating_add(funded).saturating_add(tcro("0.000021")), tcro("10"));
+    assert_eq!(agent.budget().spent(), tcro("0.000021"));
+    assert_eq!(agent.budget().reserved(), Amount::ZERO);
+    assert_eq!(agent.total_spent(), tcro("0.000021"));
+    assert_eq!(agent.stats.payments_made.load(Ordering::Relaxed), 0);
+}
+
+#[tokio::test]
+async fn dry_runs_are_simulated_and_move_no_funds() {
+    let backend = ledger().reporting_simulated(false);
+    let addr = spawn_server().await;
+    let weather = format!("http://{}/api/weather", addr);
+    let quote = format!("http://{}/api/quote", addr);
+
+    let live = agent_with(agent_config("live", backend.sender()), backend.clone());
+    assert!(!live.is_simulated());
+    assert!(!live.fetch(&quote).await.unwrap().simulated);
+    assert_eq!(backend.balance(recipient()).await.unwrap(), tcro("0.001"));
+
+    let config = AgentConfig {
+        dry_run: true,
+        ..agent_config("dry-run", backend.sender())
+    };
+    let dry = agent_with(config, backend.clone());
+    let streamed = dry.fetch(&weather).await.unwrap();
+    let quoted = dry.fetch(&quote).await.unwrap();
+
+    // Simulated proofs are not sent, so each paid request ends at its 402
+    for result in [&streamed, &quoted] {
+        assert_eq!(result.status, 402);
+        assert!(result.payment_made);
+        assert!(result.simulated);
+    }
+    // Budgets and stream tracking still apply
+    assert_eq!(dry.budget().spent(), tcro("0.361"));
+    assert_eq!(dry.active_streams().len(), 1);
+
+    // Only the live payment reached the attached backend
+    assert_eq!(backend.balance(backend.sender()).await.unwrap(), tcro("9.999"));
+    assert_eq!(backend.balance(recipient()).await.unwrap(), tcro("0.001"));
+    assert!(backend.streams().is_empty());
+}
+
+
+/// A [`MemoryBackend`] on which every address but `root` has its
+/// `createStream` reverted
+#[derive(Debug, Clone)]
+struct Reverting {
+    backend: MemoryBackend,
+    root: Address,
+}
+
+#[async_trait]
+impl PaymentBackend for Reverting {
+    fn sender(&self) -> Address {
+        self.backend.sender()
+    }
+
+    fn is_simulated(&self) -> bool {
+        self.backend.is_simulated()
+    }
+
+    fn now(&self) -> u64 {
+        self.backend.now()
+    }
+
+    fn with_wallet(&self, wallet: &AgentWallet) -> Self {
+        Reverting {
+            backend: self.backend.with_wallet(wallet),
+            root: self.root,
+        }
+    }
+
+    async fn balance(&self, account: Address) -> Result<Amount, ChainError> {
+        self.backend.balance(account).await
+    }
+
+    async fn gas_price(&self) -> Result<Amount, ChainError> {
+        self.backend.gas_price().await
+    }
+
+    async fn open_stream(
+        &self,
+        recipient: Address,
+        duration_secs: u64,
+        deposit: Amount,
+        metadata: &str,
+    ) -> Result<CreatedStream, ChainError> {
+        if self.sender() != self.root {
+            return Err(ChainError::Contract("execution reverted".to_string()));
+        }
+        self.backend.open_stream(recipient, duration_secs, deposit, metadata).await
+    }
+
+    async fn pay(&self, recipient: Address, amount: Amount) -> Result<Transfer, ChainError> {
+        self.backend.pay(recipient, amount).await
+    }
+
+    async fn cancel_stream(&self, stream_id: u64) -> Result<CancelledStream, ChainError> {
+        self.backend.cancel_stream(stream_id).await
+    }
+
+    async fn stream(&self, stream_id: u64) -> Result<StreamState, ChainError> {
+        self.backend.stream(stream_id).await
+    }
+
+    async fn find_transfer(&self, tx_hash: ethers::types::H256) -> Result<Option<LedgerTransfer>, ChainError> {
+        self.backend.find_transfer(tx_hash).await
+    }
+
+    async fn fund(&self, account: Address, value: Amount, gas_limit: u64) -> Result<Option<Transfer>, ChainError> {
+        self.backend.fund(account, value, gas_limit).await
+    }
+
+    async fn sweep(&self, to: Address) -> Result<Option<Transfer>, ChainError> {
+        self.backend.sweep(to).await
+    }
+}
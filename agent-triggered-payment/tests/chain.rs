@@ -0,0 +1,162 @@
+//! On-chain stream creation, cancellation and transfers against a local node.
+//!
+//! Start `npx hardhat node` in the repository root, deploy with
+//! `npx hardhat run scripts/deploy.js --network localhost`, then run
+//! `PAYSTREAM_TEST_CONTRACT=0x... cargo test --features test-support --test chain -- --ignored`.
+
+use std::net::SocketAddr;
+
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+
+use paystream_cro::amount::Amount;
+use paystream_cro::chain::{ChainConfig, PayStreamChain};
+use paystream_cro::payment_agent::PaymentAgent;
+use paystream_cro::test_support::{self, agent_config, agent_with, RECIPIENT};
+use paystream_cro::wallet::AgentWallet;
+
+/// Hardhat/anvil default account #0
+const DEV_KEY: &str = "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80";
+
+async fn chain() -> PayStreamChain {
+    let config = ChainConfig {
+        rpc_url: std::env::var("PAYSTREAM_TEST_RPC_URL").unwrap_or_else(|_| "http://127.0.0.1:8545".to_string()),
+        contract: std::env::var("PAYSTREAM_TEST_CONTRACT")
+            .expect("PAYSTREAM_TEST_CONTRACT must name a deployed PayStreamStream")
+            .parse()
+            .unwrap(),
+        confirmations: 1,
+    };
+    let wallet = AgentWallet::from_hex(DEV_KEY, "DEV_KEY").unwrap();
+    PayStreamChain::connect(&config, &wallet).await.expect("connect to local node")
+}
+
+async fn paid_quote(headers: HeaderMap) -> Response {
+    if let Some(hash) = headers.get("x-paystream-tx-hash") {
+        if hash.len() == 66 {
+            return (StatusCode::OK, "paid").into_response();
+        }
+    }
+    (
+        StatusCode::PAYMENT_REQUIRED,
+        [
+            ("X-Payment-Required", "true"),
+            ("X-PayStream-Mode", "per-request"),
+            ("X-PayStream-Rate", "0.001"),
+            ("X-PayStream-Recipient", RECIPIENT),
+        ],
+    )
+        .into_response()
+}
+
+async fn paid_weather(headers: HeaderMap) -> Response {
+    if headers.contains_key("x-paystream-stream-id") {
+        return (StatusCode::OK, "paid").into_response();
+    }
+    (
+        StatusCode::PAYMENT_REQUIRED,
+        [
+            ("X-Payment-Required", "true"),
+            ("X-PayStream-Mode", "streaming"),
+            ("X-PayStream-Rate", "0.0001"),
+            ("X-PayStream-Recipient", RECIPIENT),
+            ("X-PayStream-MinDeposit", "0.36"),
+        ],
+    )
+        .into_response()
+}
+
+async fn spawn_server() -> SocketAddr {
+    let app = Router::new()
+        .route("/api/weather", get(paid_weather))
+        .route("/api/quote", get(paid_quote));
+    test_support::spawn_server(app).await
+}
+
+#[tokio::test]
+#[ignore = "needs a local node with PayStreamStream deployed"]
+async fn create_stream_reads_id_from_event() {
+    let chain = chain().await;
+    let deposit: Amount = "0.36".parse().unwrap();
+
+    let first = chain.create_stream(RECIPIENT.parse().unwrap(), 3600, deposit, "{}").await.unwrap();
+    let second = chain.create_stream(RECIPIENT.parse().unwrap(), 3600, deposit, "{}").await.unwrap();
+
+    assert_eq!(first.total_amount, deposit);
+    assert_eq!(first.stop_time - first.start_time, 3600);
+    assert_eq!(second.stream_id, first.stream_id + 1);
+}
+
+#[tokio::test]
+#[ignore = "needs a local node with PayStreamStream deployed"]
+async fn cancel_stream_splits_deposit() {
+    let chain = chain().await;
+    let deposit: Amount = "0.36".parse().unwrap();
+
+    let created = chain.create_stream(RECIPIENT.parse().unwrap(), 3600, deposit, "{}").await.unwrap();
+    let cancelled = chain.cancel_stream(created.stream_id).await.unwrap();
+
+    assert_eq!(cancelled.stream_id, created.stream_id);
+    assert_eq!(cancelled.refund.saturating_add(cancelled.payout), deposit);
+    assert!(cancelled.refund > cancelled.payout);
+}
+
+#[tokio::test]
+#[ignore = "needs a local node with PayStreamStream deployed"]
+async fn transfer_waits_for_receipt() {
+    let chain = chain().await;
+    let amount: Amount = "0.001".parse().unwrap();
+
+    let transfer = chain.transfer(RECIPIENT.parse().unwrap(), amount).await.unwrap();
+
+    assert_eq!(transfer.amount, amount);
+    assert!(transfer.block_number.is_some());
+    assert!(!transfer.gas_cost.is_zero());
+
+    let found = chain.find_transfer(transfer.tx_hash).await.unwrap().unwrap();
+    assert_eq!((found.from, found.to, found.amount), (chain.sender(), RECIPIENT.parse().unwrap(), amount));
+    assert_eq!(Some(found.block_number), transfer.block_number);
+}
+
+fn agent(chain: PayStreamChain) -> PaymentAgent<PayStreamChain> {
+    agent_with(agent_config("chain-test", chain.sender()), chain)
+}
+
+#[tokio::test]
+#[ignore = "needs a local node with PayStreamStream deployed"]
+async fn agent_pays_streaming_402_on_chain() {
+    let agent = agent(chain().await);
+
+    let addr = spawn_server().await;
+    let result = agent.fetch(&format!("http://{}/api/weather", addr)).await.unwrap();
+
+    assert_eq!(result.status, 200);
+    assert!(result.stream_id.is_some_and(|id| id > 0));
+}
+
+#[tokio::test]
+#[ignore = "needs a local node with PayStreamStream deployed"]
+async fn agent_pays_per_request_402_on_chain() {
+    let agent = agent(chain().await);
+
+    let addr = spawn_server().await;
+    let result = agent.fetch(&format!("http://{}/api/quote", addr)).await.unwrap();
+
+    assert_eq!(result.status, 200);
+    assert_eq!(result.amount_spent, Some("0.001".parse().unwrap()));
+}
+
+#[tokio::test]
+#[ignore = "needs a local node with PayStreamStream deployed"]
+async fn agent_cancel_credits_refund_to_budget() {
+    let agent = agent(chain().await);
+
+    let addr = spawn_server().await;
+    let result = agent.fetch(&format!("http://{}/api/weather", addr)).await.unwrap();
+    let cancelled = agent.cancel_stream(result.stream_id.unwrap()).await.unwrap();
+
+    assert!(agent.active_streams().is_empty());
+    assert_eq!(agent.budget().spent(), cancelled.payout);
+}
@@ -0,0 +1,157 @@
+//! The full challenge → pay → retry loop against the mock provider, and the
+//! proofs it refuses.
+
+use paystream_cro::backend::PaymentBackend;
+use paystream_cro::payment_agent::AgentConfig;
+use paystream_cro::paywall::Rejection;
+use paystream_cro::test_support::{
+    agent, agent_config, agent_with, funded_backend, recipient, tcro, MockProvider, PaidRoute,
+};
+use paystream_cro::x402::{headers, HeaderDialect};
+
+#[tokio::test]
+async fn streams_in_either_dialect_and_reuses_them() {
+    for dialect in [HeaderDialect::FlowPay, HeaderDialect::PayStream] {
+        let backend = funded_backend();
+        let route = PaidRoute::streaming("/api/weather", recipient(), tcro("0.0001"), tcro("0.36"))
+            .with_dialect(dialect)
+            .with_body("sunny");
+        let provider = MockProvider::new(backend.clone()).with_route(route).start().await;
+        let agent = agent(backend.clone());
+
+        let first = agent.fetch(&provider.url("/api/weather")).await.unwrap();
+        let second = agent.fetch(&provider.url("/api/weather")).await.unwrap();
+
+        assert_eq!(first.body, "sunny");
+        assert_eq!(second.stream_id, first.stream_id);
+        assert_eq!((provider.challenged(), provider.accepted(), provider.rejected()), (1, 2, 0));
+        let stream = backend.stream(first.stream_id.unwrap()).await.unwrap();
+        assert_eq!(stream.recipient, recipient());
+        assert_eq!(stream.total_amount, tcro("0.36"));
+    }
+}
+
+#[tokio::test]
+async fn pays_per_request_in_either_dialect() {
+    for dialect in [HeaderDialect::FlowPay, HeaderDialect::PayStream] {
+        let backend = funded_backend();
+        let route = PaidRoute::per_request("/api/quote", recipient(), tcro("0.001")).with_dialect(dialect);
+        let provider = MockProvider::new(backend.clone()).with_route(route).start().await;
+        let agent = agent(backend.clone());
+
+        agent.fetch(&provider.url("/api/quote")).await.unwrap();
+        agent.fetch(&provider.url("/api/quote")).await.unwrap();
+
+        assert_eq!((provider.challenged(), provider.accepted(), provider.rejected()), (2, 2, 0));
+        assert_eq!(backend.balance(recipient()).await.unwrap(), tcro("0.002"));
+    }
+}
+
+#[tokio::test]
+async fn cancelled_stream_is_refused_and_replaced() {
+    let backend = funded_backend();
+    let route = PaidRoute::streaming("/api/weather", recipient(), tcro("0.0001"), tcro("0.36"));
+    let provider = MockProvider::new(backend.clone()).with_route(route).start().await;
+    let agent = agent(backend.clone());
+
+    let first = agent.fetch(&provider.url("/api/weather")).await.unwrap();
+    backend.cancel_stream(first.stream_id.unwrap()).await.unwrap();
+    let second = agent.fetch(&provider.url("/api/weather")).await.unwrap();
+
+    assert_eq!(second.status, 200);
+    assert_ne!(second.stream_id, first.stream_id);
+    assert_eq!(provider.rejections(), vec![Rejection::InactiveStream(first.stream_id.unwrap())]);
+    assert_eq!((provider.challenged(), provider.accepted(), provider.rejected()), (1, 2, 1));
+}
+
+#[tokio::test]
+async fn refuses_underpaid_unknown_and_replayed_transfers() {
+    let backend = funded_backend();
+    let route = PaidRoute::per_request("/api/quote", recipient(), tcro("0.001"));
+    let provider = MockProvider::new(backend.clone()).with_route(route).start().await;
+    let client = reqwest::Client::new();
+    let send = |tx_hash: String| {
+        client
+            .get(provider.url("/api/quote"))
+            .header(headers::PAYSTREAM_TX_HASH, tx_hash)
+            .send()
+    };
+
+    let underpaid = backend.pay(recipient(), tcro("0.0005")).await.unwrap();
+    let paid = backend.pay(recipient(), tcro("0.001")).await.unwrap();
+    let statuses = [
+        send(format!("{:?}", underpaid.tx_hash)).await.unwrap().status().as_u16(),
+        send("0x1234".to_string()).await.unwrap().status().as_u16(),
+        send(format!("{:?}", paid.tx_hash)).await.unwrap().status().as_u16(),
+        send(format!("{:?}", paid.tx_hash)).await.unwrap().status().as_u16(),
+    ];
+
+    assert_eq!(statuses, [402, 402, 200, 402]);
+    assert_eq!(
+        provider.rejections(),
+        vec![
+            Rejection::Underpaid {
+                paid: tcro("0.0005"),
+                price: tcro("0.001"),
+            },
+            Rejection::UnknownTransaction("0x1234".to_string()),
+            Rejection::Replayed(format!("{:?}", paid.tx_hash)),
+        ]
+    );
+    assert_eq!((provider.accepted(), provider.rejected()), (1, 3));
+}
+
+#[tokio::test]
+async fn refuses_proofs_in_another_dialect() {
+    let backend = funded_backend();
+    let route = PaidRoute::per_request("/api/quote", recipient(), tcro("0.001")).with_dialect(HeaderDialect::FlowPay);
+    let provider = MockProvider::new(backend.clone()).with_route(route).start().await;
+    let client = reqwest::Client::new();
+    let tx_hash = format!("{:?}", backend.pay(recipient(), tcro("0.001")).await.unwrap().tx_hash);
+    let send = |header: &'static str| client.get(provider.url("/api/quote")).header(header, tx_hash.clone()).send();
+
+    assert_eq!(send(headers::PAYSTREAM_TX_HASH).await.unwrap().status().as_u16(), 402);
+    assert_eq!(send(headers::FLOWPAY_TX_HASH).await.unwrap().status().as_u16(), 200);
+    assert_eq!(
+        provider.rejections(),
+        vec![Rejection::WrongDialect {
+            expected: HeaderDialect::FlowPay,
+            got: HeaderDialect::PayStream,
+        }]
+    );
+}
+
+#[tokio::test]
+async fn dry_runs_stop_at_the_402_with_the_simulated_proof() {
+    let backend = funded_backend();
+    let provider = MockProvider::new(backend.clone())
+        .with_route(PaidRoute::streaming("/api/weather", recipient(), tcro("0.0001"), tcro("0.36")))
+        .with_route(PaidRoute::per_request("/api/quote", recipient(), tcro("0.001")))
+        .start()
+        .await;
+    // A real stream #1, which a simulated stream #1 would pass for
+    backend.open_stream(recipient(), 3600, tcro("0.36"), "{}").await.unwrap();
+    let config = AgentConfig {
+        dry_run: true,
+        ..agent_config("dry-run", backend.sender())
+    };
+    let agent = agent_with(config, backend.clone());
+
+    let streamed = agent.fetch(&provider.url("/api/weather")).await.unwrap();
+    let reused = agent.fetch(&provider.url("/api/weather")).await.unwrap();
+    let quoted = agent.fetch(&provider.url("/api/quote")).await.unwrap();
+
+    for result in [&streamed, &reused, &quoted] {
+        assert_eq!(result.status, 402);
+        assert!(result.simulated);
+        assert!(result.negotiation.is_some());
+        assert!(result.proof.is_some());
+    }
+    assert!(streamed.payment_made && quoted.payment_made && !reused.payment_made);
+    assert_eq!(streamed.stream_id, Some(1));
+    assert_eq!(reused.stream_id, Some(1));
+
+    // Every request went out bare, and nothing moved on the provider's ledger
+    assert_eq!((provider.challenged(), provider.accepted(), provider.rejected()), (3, 0, 0));
+    assert_eq!(backend.balance(backend.sender()).await.unwrap(), tcro("9.64"));
+}
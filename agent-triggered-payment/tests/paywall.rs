@@ -0,0 +1,232 @@
+//! The paywall layer in front of an axum router: pricing, challenges,
+//! verified payments in extensions, and a pluggable verifier. Also the
+//! stream verifier's checks and cache, and the transfer verifier's checks
+//! and replay set.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use axum::routing::get;
+use axum::{Extension, Router};
+
+use paystream_cro::address::Address;
+use paystream_cro::amount::Amount;
+use paystream_cro::backend::{MemoryBackend, PaymentBackend};
+use paystream_cro::paywall::{
+    BackendVerifier, PaywallConfig, PaywallLayer, ProofVerifier, Rejection, RoutePrice, StreamVerifier, TransferVerifier,
+    VerifiedPayment,
+};
+use paystream_cro::test_support::{agent, funded_backend, recipient, tcro, SENDER};
+use paystream_cro::x402::{headers, HeaderDialect, PaymentMode, PaymentProof, X402PaymentRequirement};
+
+fn pricing() -> PaywallConfig {
+    PaywallConfig::new(recipient())
+        .with_route(RoutePrice::exact("/api/weather", PaymentMode::Streaming, tcro("0.0001")).with_min_deposit(tcro("0.36")))
+        .with_route(RoutePrice::prefix("/api/", PaymentMode::PerRequest, tcro("0.01")))
+        .with_route(RoutePrice::prefix("/api/premium/", PaymentMode::PerRequest, tcro("0.001")))
+}
+
+async fn payer(Extension(payment): Extension<VerifiedPayment>) -> String {
+    format!("{:?} from {}", payment.mode, payment.payer)
+}
+
+async fn spawn_server<V: ProofVerifier>(verifier: V) -> SocketAddr {
+    let app = Router::new()
+        .route("/api/weather", get(payer))
+        .route("/api/premium/report", get(payer))
+        .route("/free", get(|| async { "free" }))
+        .layer(PaywallLayer::new(pricing(), verifier));
+    paystream_cro::test_support::spawn_server(app).await
+}
+
+#[tokio::test]
+async fn challenges_priced_routes_only() {
+    let addr = spawn_server(BackendVerifier::new(MemoryBackend::new(Address::ZERO)).with_cache_ttl(Duration::ZERO)).await;
+    let client = reqwest::Client::new();
+
+    let free = client.get(format!("http://{}/free", addr)).send().await.unwrap();
+    assert_eq!(free.status().as_u16(), 200);
+
+    let weather = client.get(format!("http://{}/api/weather", addr)).send().await.unwrap();
+    assert_eq!(weather.status().as_u16(), 402);
+    let requirement = X402PaymentRequirement::from_header_map(weather.headers()).unwrap();
+    assert_eq!(requirement.mode, PaymentMode::Streaming);
+    assert_eq!(requirement.rate_per_second, Some(tcro("0.0001")));
+    assert_eq!(requirement.min_deposit, Some(tcro("0.36")));
+
+    // The longest matching prefix sets the price
+    let report = client.get(format!("http://{}/api/premium/report", addr)).send().await.unwrap();
+    let requirement = X402PaymentRequirement::from_header_map(report.headers()).unwrap();
+    assert_eq!(requirement.amount, Some(tcro("0.001")));
+    assert_eq!(requirement.recipient, recipient());
+}
+
+#[tokio::test]
+async fn verified_payment_reaches_the_handler() {
+    let backend = funded_backend();
+    let addr = spawn_server(BackendVerifier::new(backend.clone()).with_cache_ttl(Duration::ZERO)).await;
+    let agent = agent(backend.clone());
+
+    let streamed = agent.fetch(&format!("http://{}/api/weather", addr)).await.unwrap();
+    let paid = agent.fetch(&format!("http://{}/api/premium/report", addr)).await.unwrap();
+
+    assert_eq!(streamed.body, format!("Streaming from {}", backend.sender()));
+    assert_eq!(paid.body, format!("PerRequest from {}", backend.sender()));
+    assert_eq!(backend.balance(recipient()).await.unwrap(), tcro("0.001"));
+}
+
+#[tokio::test]
+async fn refused_and_unverifiable_proofs() {
+    struct Unreachable;
+
+    #[async_trait]
+    impl ProofVerifier for Unreachable {
+        async fn verify(&self, _: &X402PaymentRequirement, _: &PaymentProof) -> Result<VerifiedPayment, Rejection> {
+            Err(Rejection::Unavailable("node is down".to_string()))
+        }
+    }
+
+    let client = reqwest::Client::new();
+    let refusing = spawn_server(BackendVerifier::new(MemoryBackend::new(Address::ZERO)).with_cache_ttl(Duration::ZERO)).await;
+    let unreachable = spawn_server(Unreachable).await;
+    let send = |addr: SocketAddr| {
+        client
+            .get(format!("http://{}/api/weather", addr))
+            .header(headers::PAYSTREAM_STREAM_ID, "7")
+            .send()
+    };
+
+    assert_eq!(send(refusing).await.unwrap().status().as_u16(), 402);
+    assert_eq!(send(unreachable).await.unwrap().status().as_u16(), 503);
+}
+
+fn weather_requirement() -> X402PaymentRequirement {
+    let config = pricing();
+    config.requirement(config.route_for("/api/weather").unwrap())
+}
+
+fn stream_proof(stream_id: u64) -> PaymentProof {
+    PaymentProof::streaming(stream_id, Amount::ZERO, HeaderDialect::PayStream)
+}
+
+#[tokio::test]
+async fn stream_verifier_checks_more_than_is_active() {
+    let backend = funded_backend();
+    backend.set_time(1_700_000_000);
+    let verifier = StreamVerifier::new(backend.clone())
+        .with_cache_ttl(Duration::ZERO)
+        .with_min_remaining_secs(60);
+    let requirement = weather_requirement();
+    let someone_else: Address = SENDER.parse().unwrap();
+    let check = async |stream_id| verifier.verify(&requirement, &stream_proof(stream_id)).await;
+
+    let good = backend.open_stream(recipient(), 3600, tcro("0.36"), "{}").await.unwrap().stream_id;
+    let elsewhere = backend.open_stream(someone_else, 3600, tcro("0.36"), "{}").await.unwrap().stream_id;
+    let slow = backend.open_stream(recipient(), 36_000, tcro("0.36"), "{}").await.unwrap().stream_id;
+    let small = backend.open_stream(recipient(), 360, tcro("0.036"), "{}").await.unwrap().stream_id;
+
+    let payment = check(good).await.unwrap();
+    assert_eq!((payment.stream_id, payment.payer), (Some(good), backend.sender()));
+    assert_eq!(check(elsewhere).await, Err(Rejection::WrongRecipient(someone_else)));
+    assert_eq!(
+        check(slow).await,
+        Err(Rejection::RateTooLow {
+            flow_rate: tcro("0.00001"),
+            rate: tcro("0.0001"),
+        })
+    );
+    assert_eq!(
+        check(small).await,
+        Err(Rejection::Underpaid {
+            paid: tcro("0.036"),
+            price: tcro("0.36"),
+        })
+    );
+    assert_eq!(check(99).await, Err(Rejection::UnknownStream(99)));
+
+    // 30 seconds left cannot cover the 60 required
+    backend.advance(3570);
+    assert_eq!(
+        check(good).await,
+        Err(Rejection::InsufficientBalance {
+            remaining: tcro("0.003"),
+            needed: tcro("0.006"),
+        })
+    );
+    backend.advance(30);
+    assert_eq!(check(good).await, Err(Rejection::ExpiredStream(good)));
+
+    backend.cancel_stream(good).await.unwrap();
+    assert_eq!(check(good).await, Err(Rejection::InactiveStream(good)));
+    assert_eq!(
+        verifier
+            .verify(&requirement, &PaymentProof::per_request("0x1234", Amount::ZERO, HeaderDialect::PayStream))
+            .await,
+        Err(Rejection::WrongMode {
+            expected: PaymentMode::Streaming,
+        })
+    );
+}
+
+#[tokio::test]
+async fn stream_verifier_caches_reads() {
+    let backend = funded_backend();
+    let cached = StreamVerifier::new(backend.clone());
+    let uncached = StreamVerifier::new(backend.clone()).with_cache_ttl(Duration::ZERO);
+    let requirement = weather_requirement();
+
+    let stream_id = backend.open_stream(recipient(), 3600, tcro("0.36"), "{}").await.unwrap().stream_id;
+    cached.verify(&requirement, &stream_proof(stream_id)).await.unwrap();
+    backend.cancel_stream(stream_id).await.unwrap();
+
+    // Within the cache window the earlier read still answers
+    assert!(cached.verify(&requirement, &stream_proof(stream_id)).await.is_ok());
+    assert_eq!(
+        uncached.verify(&requirement, &stream_proof(stream_id)).await,
+        Err(Rejection::InactiveStream(stream_id))
+    );
+}
+
+#[tokio::test]
+async fn transfer_verifier_checks_the_ledger_and_refuses_replays() {
+    let backend = funded_backend();
+    let config = pricing();
+    let requirement = config.requirement(config.route_for("/api/premium/report").unwrap());
+    let someone_else: Address = SENDER.parse().unwrap();
+    let tx_proof = |tx_hash: String| PaymentProof::per_request(&tx_hash, Amount::ZERO, HeaderDialect::PayStream);
+    let hash = |tx_hash: ethers::types::H256| format!("{:?}", tx_hash);
+
+    let good = backend.pay(recipient(), tcro("0.001")).await.unwrap().tx_hash;
+    let short = backend.pay(recipient(), tcro("0.0005")).await.unwrap().tx_hash;
+    let elsewhere = backend.pay(someone_else, tcro("0.001")).await.unwrap().tx_hash;
+    let earlier = backend.pay(recipient(), tcro("0.001")).await.unwrap().tx_hash;
+    let verifier = TransferVerifier::new(backend.clone()).with_spent([earlier]);
+    let check = async |tx_hash| verifier.verify(&requirement, &tx_proof(hash(tx_hash))).await;
+
+    let payment = check(good).await.unwrap();
+    assert_eq!((payment.payer, payment.amount), (backend.sender(), tcro("0.001")));
+    assert_eq!(check(good).await, Err(Rejection::Replayed(hash(good))));
+    assert_eq!(check(earlier).await, Err(Rejection::Replayed(hash(earlier))));
+    assert_eq!(
+        check(short).await,
+        Err(Rejection::Underpaid {
+            paid: tcro("0.0005"),
+            price: tcro("0.001"),
+        })
+    );
+    assert_eq!(check(elsewhere).await, Err(Rejection::WrongRecipient(someone_else)));
+
+    let unknown = ethers::types::H256::repeat_byte(7);
+    assert_eq!(check(unknown).await, Err(Rejection::UnknownTransaction(hash(unknown))));
+    assert_eq!(
+        verifier.verify(&requirement, &tx_proof("not a hash".to_string())).await,
+        Err(Rejection::UnknownTransaction("not a hash".to_string()))
+    );
+    assert_eq!(
+        verifier.verify(&requirement, &stream_proof(1)).await,
+        Err(Rejection::WrongMode {
+            expected: PaymentMode::PerRequest,
+        })
+    );
+}
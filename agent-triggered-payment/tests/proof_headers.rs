@@ -0,0 +1,118 @@
+//! Retry-with-proof against a local 402 server, once per header dialect.
+
+use std::net::SocketAddr;
+
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+
+use paystream_cro::test_support::{self, agent, funded_backend, RECIPIENT};
+
+async fn paystream_stream(headers: HeaderMap) -> Response {
+    if headers.contains_key("x-paystream-stream-id") {
+        return (StatusCode::OK, "paid").into_response();
+    }
+    (
+        StatusCode::PAYMENT_REQUIRED,
+        [
+            ("X-Payment-Required", "true"),
+            ("X-PayStream-Mode", "streaming"),
+            ("X-PayStream-Rate", "0.0001"),
+            ("X-PayStream-Recipient", RECIPIENT),
+            ("X-PayStream-Currency", "TCRO"),
+            ("X-PayStream-MinDeposit", "0.36"),
+        ],
+    )
+        .into_response()
+}
+
+async fn paystream_direct(headers: HeaderMap) -> Response {
+    if headers.contains_key("x-paystream-tx-hash") {
+        return (StatusCode::OK, "paid").into_response();
+    }
+    (
+        StatusCode::PAYMENT_REQUIRED,
+        [
+            ("X-Payment-Required", "true"),
+            ("X-PayStream-Mode", "per-request"),
+            ("X-PayStream-Rate", "0.01"),
+            ("X-PayStream-Recipient", RECIPIENT),
+        ],
+    )
+        .into_response()
+}
+
+async fn flowpay_stream(headers: HeaderMap) -> Response {
+    if headers.contains_key("x-flowpay-stream") {
+        return (StatusCode::OK, "paid").into_response();
+    }
+    (
+        StatusCode::PAYMENT_REQUIRED,
+        [
+            ("X-Payment-Required", "true"),
+            ("X-FlowPay-Mode", "streaming"),
+            ("X-FlowPay-Rate", "0.0001"),
+            ("X-FlowPay-Recipient", RECIPIENT),
+            ("X-FlowPay-MinDeposit", "1.00"),
+        ],
+    )
+        .into_response()
+}
+
+async fn flowpay_direct(headers: HeaderMap) -> Response {
+    if headers.contains_key("x-payment-txhash") {
+        return (StatusCode::OK, "paid").into_response();
+    }
+    (
+        StatusCode::PAYMENT_REQUIRED,
+        [
+            ("X-Payment-Required", "true"),
+            ("X-FlowPay-Mode", "per-request"),
+            ("X-FlowPay-Amount", "0.005"),
+            ("X-FlowPay-Recipient", RECIPIENT),
+        ],
+    )
+        .into_response()
+}
+
+async fn spawn_server() -> SocketAddr {
+    let app = Router::new()
+        .route("/paystream/stream", get(paystream_stream))
+        .route("/paystream/direct", get(paystream_direct))
+        .route("/flowpay/stream", get(flowpay_stream))
+        .route("/flowpay/direct", get(flowpay_direct));
+    test_support::spawn_server(app).await
+}
+
+async fn assert_paid(path: &str) {
+    let addr = spawn_server().await;
+    let result = agent(funded_backend())
+        .fetch(&format!("http://{}{}", addr, path))
+        .await
+        .expect("fetch failed");
+
+    assert_eq!(result.status, 200, "retry to {} was not accepted", path);
+    assert!(result.payment_made);
+    assert_eq!(result.body, "paid");
+}
+
+#[tokio::test]
+async fn paystream_streaming_retry_is_accepted() {
+    assert_paid("/paystream/stream").await;
+}
+
+#[tokio::test]
+async fn paystream_per_request_retry_is_accepted() {
+    assert_paid("/paystream/direct").await;
+}
+
+#[tokio::test]
+async fn flowpay_streaming_retry_is_accepted() {
+    assert_paid("/flowpay/stream").await;
+}
+
+#[tokio::test]
+async fn flowpay_per_request_retry_is_accepted() {
+    assert_paid("/flowpay/direct").await;
+}
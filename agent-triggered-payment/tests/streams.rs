@@ -0,0 +1,129 @@
+//! Stream upkeep on the ledger's clock: idle streams are reaped and streams
+//! about to run dry are topped up.
+
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use paystream_cro::backend::PaymentBackend;
+use paystream_cro::payment_agent::AgentConfig;
+use paystream_cro::test_support::{agent_config, agent_with, funded_backend, recipient, tcro, MockProvider, PaidRoute};
+
+const START: u64 = 1_700_000_000;
+
+fn weather() -> PaidRoute {
+    PaidRoute::streaming("/api/weather", recipient(), tcro("0.0001"), tcro("0.36"))
+}
+
+#[tokio::test]
+async fn idle_streams_are_reaped_on_the_ledger_clock() {
+    let backend = funded_backend();
+    backend.set_time(START);
+    let provider = MockProvider::new(backend.clone()).with_route(weather()).start().await;
+    let config = AgentConfig {
+        idle_stream_timeout: Some(Duration::from_secs(300)),
+        ..agent_config("streams-test", backend.sender())
+    };
+    let agent = agent_with(config, backend.clone());
+
+    let opened = agent.fetch(&provider.url("/api/weather")).await.unwrap();
+    backend.advance(299);
+    assert!(agent.reap_idle_streams().await.is_empty());
+
+    backend.advance(1);
+    let reaped = agent.reap_idle_streams().await;
+
+    assert_eq!(reaped.len(), 1);
+    assert_eq!(reaped[0].stream_id, opened.stream_id.unwrap());
+    assert_eq!(reaped[0].refund, tcro("0.33"));
+    assert_eq!(*agent.stats.idle_reclaimed.lock().unwrap(), tcro("0.33"));
+    assert_eq!(agent.stats.idle_streams_cancelled.load(Ordering::Relaxed), 1);
+    assert!(agent.active_streams().is_empty());
+    assert!(!backend.stream(opened.stream_id.unwrap()).await.unwrap().is_active);
+}
+
+#[tokio::test]
+async fn streams_in_use_are_topped_up_on_the_ledger_clock() {
+    let backend = funded_backend();
+    backend.set_time(START);
+    let provider = MockProvider::new(backend.clone()).with_route(weather()).start().await;
+    let config = AgentConfig {
+        stream_top_up_lead: Some(Duration::from_secs(600)),
+        ..agent_config("streams-test", backend.sender())
+    };
+    let agent = agent_with(config, backend.clone());
+
+    let opened = agent.fetch(&provider.url("/api/weather")).await.unwrap();
+    backend.advance(100);
+    agent.fetch(&provider.url("/api/weather")).await.unwrap();
+    assert!(agent.top_up_streams().await.is_empty());
+
+    // The 3600 second stream has 500 seconds left, inside the lead
+    backend.advance(3000);
+    let replacements = agent.top_up_streams().await;
+
+    assert_eq!(replacements.len(), 1);
+    assert_ne!(Some(replacements[0].stream_id), opened.stream_id);
+    assert_eq!(replacements[0].start_time, START + 3100);
+    assert_eq!(agent.stats.streams_topped_up.load(Ordering::Relaxed), 1);
+    let next = agent.fetch(&provider.url("/api/weather")).await.unwrap();
+    assert_eq!(next.stream_id, Some(replacements[0].stream_id));
+
+    // The old stream is still open on chain, so it stays tracked, and is not
+    // topped up a second time
+    let mut tracked: Vec<_> = agent.active_streams().iter().map(|stream| stream.stream_id).collect();
+    tracked.sort_unstable();
+    assert_eq!(tracked, vec![opened.stream_id.unwrap(), replacements[0].stream_id]);
+    assert!(agent.top_up_streams().await.is_empty());
+
+    let cancelled = agent.cancel_all_streams().await;
+    assert_eq!(cancelled.len(), 2);
+    assert!(cancelled.iter().all(|(_, outcome)| outcome.is_ok()));
+    assert!(agent.active_streams().is_empty());
+    assert!(!backend.stream(opened.stream_id.unwrap()).await.unwrap().is_active);
+}
+
+#[tokio::test]
+async fn streams_are_attached_only_to_their_own_route() {
+    let backend = funded_backend();
+    backend.set_time(START);
+    let provider = MockProvider::new(backend.clone())
+        .with_route(weather())
+        .with_route(PaidRoute::per_request("/api/quote", recipient(), tcro("0.001")))
+        .start()
+        .await;
+    let agent = agent_with(agent_config("streams-test", backend.sender()), backend.clone());
+
+    let streamed = agent.fetch(&provider.url("/api/weather")).await.unwrap();
+    agent.fetch(&provider.url("/api/quote")).await.unwrap();
+    agent.fetch(&provider.url("/api/quote")).await.unwrap();
+    let reused = agent.fetch(&provider.url("/api/weather?city=lagos")).await.unwrap();
+
+    // The quotes are paid per request without the stream being offered or forgotten
+    assert_eq!(reused.stream_id, streamed.stream_id);
+    assert_eq!(provider.rejections(), vec![]);
+    assert_eq!((provider.challenged(), provider.accepted()), (3, 4));
+    assert_eq!(
+        agent.active_streams().iter().map(|stream| stream.stream_id).collect::<Vec<_>>(),
+        vec![streamed.stream_id.unwrap()]
+    );
+
+    let cancelled = agent.cancel_all_streams().await;
+    assert_eq!(cancelled.len(), 1);
+    assert_eq!(cancelled[0].0, streamed.stream_id.unwrap());
+    assert_eq!(cancelled[0].1.as_ref().unwrap().refund, tcro("0.36"));
+}
+
+#[tokio::test]
+async fn active_streams_drop_at_their_stop_time() {
+    let backend = funded_backend();
+    backend.set_time(START);
+    let provider = MockProvider::new(backend.clone()).with_route(weather()).start().await;
+    let agent = agent_with(agent_config("streams-test", backend.sender()), backend.clone());
+
+    agent.fetch(&provider.url("/api/weather")).await.unwrap();
+    backend.advance(3599);
+    assert_eq!(agent.active_streams().len(), 1);
+
+    backend.advance(1);
+    assert!(agent.active_streams().is_empty());
+}